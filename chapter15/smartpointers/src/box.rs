@@ -12,7 +12,7 @@
 */
 
 
-fn box(){
+pub fn using_box(){
     /*
         Use box when:
         1. Size of type not known at compile time.
@@ -32,11 +32,11 @@ fn box(){
         The compiler needs some indirection (i.e a pointer like Box) to hold the recursive value, because the pointers
         value is of fixed size, unlike a List.
     */
-    // Not allowed!!!
-    enum List {
-        Cons(i32, List),
-        Nil,
-    }
+    // Not allowed!!! (error[E0072]: recursive type `List` has infinite size)
+    // enum List {
+    //     Cons(i32, List),
+    //     Nil,
+    // }
     // Allowed!!!
     enum List {
         Cons(i32, Box<List>),
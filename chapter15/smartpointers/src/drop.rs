@@ -16,7 +16,7 @@ impl Drop for CustomSmartPointer {
     }
 }
 
-fn main() {
+pub fn main() {
     let c = CustomSmartPointer {
         data: String::from("my stuff"),
     };
@@ -24,6 +24,6 @@ fn main() {
         data: String::from("other stuff"),
     };
     println!("CustomSmartPointers created.");
-    c.drop(); // NOT ALLOWED
+    // c.drop(); // NOT ALLOWED (error[E0040]: explicit use of destructor method)
     drop(c); // ALLOWED
 }
@@ -0,0 +1,20 @@
+/*
+    The examples of chapter 15, one module per smart pointer. Most of the functions are
+    examples from the book that nothing calls; building them as a library keeps them
+    compiling, and lets cargo test run the tests of the list in rc and the trackers in
+    refcell.
+
+    The examples bind values only to show who owns them and define types they never
+    read, so the lints for unused code are turned off for them, and only for them.
+*/
+
+#[allow(dead_code, unused_variables)]
+pub mod r#box;
+#[allow(dead_code)]
+pub mod deref;
+#[allow(unused_variables)]
+pub mod drop;
+pub mod rc;
+pub mod refcell;
+#[allow(dead_code)]
+pub mod referencecycles;
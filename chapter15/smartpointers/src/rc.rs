@@ -13,34 +13,44 @@
     Rc<T> is only for single threaded applications.
 */
 
-enum List {
-    Cons(i32, Box<List>),
-    Nil,
-}
+pub mod list;
 
-use crate::List::{Cons, Nil};
+/*
+    Both versions define a List, so the Box<T> version gets a module of its own.
+*/
+#[allow(dead_code, unused_variables)]
+pub mod with_box {
+    enum List {
+        Cons(i32, Box<List>),
+        Nil,
+    }
 
-fn main() {
-    let a = Cons(5, Box::new(Cons(10, Box::new(Nil))));
-    let b = Cons(3, Box::new(a));
-    let c = Cons(4, Box::new(a));
-    /*
-        To get this code to compile, we could change Cons to make it hold references instead,
-        but then we would need to specify lifetime parameters.
-    
-        Instead, we can change our definition of List to use Rc<T> in place of Box<T>.
-    */
+    use self::List::{Cons, Nil};
+
+    pub fn main() {
+        let a = Cons(5, Box::new(Cons(10, Box::new(Nil))));
+        let b = Cons(3, Box::new(a));
+        // let c = Cons(4, Box::new(a)); // error[E0382]: use of moved value: `a`
+        /*
+            To get this code to compile, we could change Cons to make it hold references instead,
+            but then we would need to specify lifetime parameters.
+
+            Instead, we can change our definition of List to use Rc<T> in place of Box<T>.
+        */
+    }
 }
 
+#[allow(dead_code)]
 enum List {
     Cons(i32, Rc<List>),
     Nil,
 }
 
-use crate::List::{Cons, Nil};
+use self::List::{Cons, Nil};
 use std::rc::Rc;
 
-fn main() {
+#[allow(unused_variables)]
+pub fn main() {
     let a = Rc::new(Cons(5, Rc::new(Cons(10, Rc::new(Nil)))));
     let b = Cons(3, Rc::clone(&a));
     let c = Cons(4, Rc::clone(&a));
@@ -0,0 +1,470 @@
+/*
+    The Cons(i32, Rc<List>) list above grown into a small library. Like b and c sharing
+    a, lists share their tails through Rc instead of copying them:
+
+        let a: List<i32> = [5, 10].into_iter().collect();
+        let b = a.push_front(3);
+        let c = a.push_front(4);
+
+    Here b and c each have one node of their own, in front of the two nodes of a.
+
+    Because of the sharing, a list is never changed once made (it is persistent):
+    push_front, tail, map and the rest return a new list and leave the old one as it
+    was, and cloning a list only clones an Rc. Operations that have to rebuild nodes,
+    like reverse, need T: Clone to copy the values into them.
+
+    Like any Rc, such a list can't be sent to another thread. The list is generic over
+    the kind of pointer between its nodes, so the same code also builds ArcList, whose
+    nodes are linked by Arc and can be shared by many threads at once. List<T> on its
+    own still means the Rc one.
+*/
+
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/*
+    A kind of reference-counted pointer: Of<T> is the pointer to a T, like Rc<T>.
+    The Rc and Arc types can't be named without their T, so the list names the kind
+    instead, and gets its pointer type through Of.
+*/
+pub trait Pointer {
+    type Of<T>: Deref<Target = T> + Clone;
+
+    fn new<T>(value: T) -> Self::Of<T>;
+    fn into_inner<T>(this: Self::Of<T>) -> Option<T>;
+    fn ptr_eq<T>(a: &Self::Of<T>, b: &Self::Of<T>) -> bool;
+}
+
+/*
+    These are only names for a kind of pointer, never values, so they have no variants.
+*/
+pub enum RcPointer {}
+
+pub enum ArcPointer {}
+
+impl Pointer for RcPointer {
+    type Of<T> = Rc<T>;
+
+    fn new<T>(value: T) -> Rc<T> {
+        Rc::new(value)
+    }
+
+    fn into_inner<T>(this: Rc<T>) -> Option<T> {
+        Rc::into_inner(this)
+    }
+
+    fn ptr_eq<T>(a: &Rc<T>, b: &Rc<T>) -> bool {
+        Rc::ptr_eq(a, b)
+    }
+}
+
+impl Pointer for ArcPointer {
+    type Of<T> = Arc<T>;
+
+    fn new<T>(value: T) -> Arc<T> {
+        Arc::new(value)
+    }
+
+    fn into_inner<T>(this: Arc<T>) -> Option<T> {
+        Arc::into_inner(this)
+    }
+
+    fn ptr_eq<T>(a: &Arc<T>, b: &Arc<T>) -> bool {
+        Arc::ptr_eq(a, b)
+    }
+}
+
+pub struct List<T, P: Pointer = RcPointer> {
+    head: Option<P::Of<Node<T, P>>>,
+    len: usize,
+}
+
+/*
+    An ArcList is Send and Sync when T is, just as Arc<T> needs T to be both.
+*/
+pub type ArcList<T> = List<T, ArcPointer>;
+
+struct Node<T, P: Pointer> {
+    value: T,
+    next: Option<P::Of<Node<T, P>>>,
+}
+
+impl<T, P: Pointer> List<T, P> {
+    pub fn new() -> List<T, P> {
+        List { head: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    /*
+        A new list with value in front of this one, which it shares in full.
+    */
+    pub fn push_front(&self, value: T) -> List<T, P> {
+        List {
+            head: Some(P::new(Node {
+                value,
+                next: self.head.clone(),
+            })),
+            len: self.len + 1,
+        }
+    }
+
+    pub fn head(&self) -> Option<&T> {
+        self.head.as_ref().map(|node| &node.value)
+    }
+
+    /*
+        Everything after the head, or None for an empty list. The tail is shared
+        with this list, not copied.
+    */
+    pub fn tail(&self) -> Option<List<T, P>> {
+        self.head.as_ref().map(|node| List {
+            head: node.next.clone(),
+            len: self.len - 1,
+        })
+    }
+
+    pub fn iter(&self) -> Iter<'_, T, P> {
+        Iter {
+            next: self.head.as_deref(),
+            len: self.len,
+        }
+    }
+
+    /*
+        Whether the two lists start at the very same node, so they are one list, not
+        just equal ones.
+    */
+    pub fn ptr_eq(&self, other: &List<T, P>) -> bool {
+        match (&self.head, &other.head) {
+            (Some(a), Some(b)) => P::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    pub fn fold<B, F>(&self, init: B, f: F) -> B
+    where
+        F: FnMut(B, &T) -> B,
+    {
+        self.iter().fold(init, f)
+    }
+
+    pub fn map<U, F>(&self, f: F) -> List<U, P>
+    where
+        F: FnMut(&T) -> U,
+    {
+        self.iter().map(f).collect()
+    }
+
+    /*
+        The values that pass keep their order. The longest tail in which every value
+        passes is shared with this list instead of being copied.
+    */
+    pub fn filter<F>(&self, mut keep: F) -> List<T, P>
+    where
+        T: Clone,
+        F: FnMut(&T) -> bool,
+    {
+        let mut kept = vec![];
+        let mut shared = List::new();
+        let mut rest = self.clone();
+        while let Some(node) = rest.head.clone() {
+            let tail = rest.tail().unwrap();
+            if keep(&node.value) {
+                if shared.is_empty() {
+                    shared = rest;
+                }
+            } else if !shared.is_empty() {
+                // A value was dropped, so the values kept since can't be shared.
+                kept.extend(shared.iter().take(shared.len - rest.len).cloned());
+                shared = List::new();
+            }
+            rest = tail;
+        }
+        kept.into_iter()
+            .rev()
+            .fold(shared, |list, value| list.push_front(value))
+    }
+
+    pub fn reverse(&self) -> List<T, P>
+    where
+        T: Clone,
+    {
+        self.fold(List::new(), |list, value| list.push_front(value.clone()))
+    }
+
+    /*
+        This list followed by other. The nodes of this list are copied, since their
+        last one has to point somewhere new; other is shared.
+    */
+    pub fn append(&self, other: &List<T, P>) -> List<T, P>
+    where
+        T: Clone,
+    {
+        let values: Vec<&T> = self.iter().collect();
+        values
+            .into_iter()
+            .rev()
+            .fold(other.clone(), |list, value| list.push_front(value.clone()))
+    }
+}
+
+/*
+    The compiler-made drop would drop the first node, which drops the second, and so on,
+    one nested call per node, which overflows the stack for a long enough list. Instead
+    the nodes are taken apart one at a time in a loop. It stops at the first node some
+    other list still holds, since that one and everything after it stay alive.
+
+    into_inner rather than try_unwrap matters for ArcList: when two threads drop the
+    last two lists holding a node, both try_unwraps can fail, and the node is then
+    dropped the compiler's way after all. into_inner gives the node to exactly one
+    of them.
+*/
+impl<T, P: Pointer> Drop for List<T, P> {
+    fn drop(&mut self) {
+        let mut next = self.head.take();
+        while let Some(node) = next {
+            match P::into_inner(node) {
+                Some(mut node) => next = node.next.take(),
+                None => break,
+            }
+        }
+    }
+}
+
+/*
+    Cloning shares every node, so it doesn't need T: Clone.
+*/
+impl<T, P: Pointer> Clone for List<T, P> {
+    fn clone(&self) -> List<T, P> {
+        List {
+            head: self.head.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<T, P: Pointer> Default for List<T, P> {
+    fn default() -> List<T, P> {
+        List::new()
+    }
+}
+
+/*
+    The values come out in the order the iterator gives them, so the first value
+    becomes the head.
+*/
+impl<T, P: Pointer> FromIterator<T> for List<T, P> {
+    fn from_iter<I: IntoIterator<Item = T>>(values: I) -> List<T, P> {
+        let values: Vec<T> = values.into_iter().collect();
+        values
+            .into_iter()
+            .rev()
+            .fold(List::new(), |list, value| list.push_front(value))
+    }
+}
+
+impl<T: PartialEq, P: Pointer> PartialEq for List<T, P> {
+    fn eq(&self, other: &List<T, P>) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq, P: Pointer> Eq for List<T, P> {}
+
+impl<T: fmt::Debug, P: Pointer> fmt::Debug for List<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+pub struct Iter<'a, T, P: Pointer = RcPointer> {
+    next: Option<&'a Node<T, P>>,
+    len: usize,
+}
+
+impl<'a, T, P: Pointer> Iterator for Iter<'a, T, P> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.next?;
+        self.next = node.next.as_deref();
+        self.len -= 1;
+        Some(&node.value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T, P: Pointer> ExactSizeIterator for Iter<'_, T, P> {}
+
+impl<'a, T, P: Pointer> IntoIterator for &'a List<T, P> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, P>;
+
+    fn into_iter(self) -> Iter<'a, T, P> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn lists_share_their_tails() {
+        let a: List<i32> = [5, 10].into_iter().collect();
+        let b = a.push_front(3);
+        let c = a.push_front(4);
+
+        assert_eq!(b, [3, 5, 10].into_iter().collect());
+        assert_eq!(c.head(), Some(&4));
+        assert!(b.tail().unwrap().ptr_eq(&a));
+        assert!(c.tail().unwrap().ptr_eq(&a));
+        assert_eq!(a.len(), 2);
+        assert_eq!(format!("{a:?}"), "[5, 10]");
+    }
+
+    #[test]
+    fn empty_list_has_no_head_or_tail() {
+        let list: List<String> = List::new();
+        assert!(list.is_empty());
+        assert_eq!(list.head(), None);
+        assert!(list.tail().is_none());
+        assert_eq!(list.iter().next(), None);
+    }
+
+    #[test]
+    fn map_filter_and_fold() {
+        let list: List<i32> = (1..=6).collect();
+
+        let squares = list.map(|n| n * n);
+        assert_eq!(
+            squares.iter().copied().collect::<Vec<_>>(),
+            [1, 4, 9, 16, 25, 36]
+        );
+        assert_eq!(list.fold(0, |sum, n| sum + n), 21);
+
+        let odd = list.filter(|n| n % 2 == 1);
+        assert_eq!(odd, [1, 3, 5].into_iter().collect());
+        assert_eq!(list.len(), 6);
+    }
+
+    #[test]
+    fn filter_shares_the_tail_that_passes() {
+        let tail: List<i32> = [4, 6, 8].into_iter().collect();
+        let list = tail.push_front(3).push_front(2);
+
+        let even = list.filter(|n| n % 2 == 0);
+        assert_eq!(even, [2, 4, 6, 8].into_iter().collect());
+        assert!(even.tail().unwrap().ptr_eq(&tail));
+
+        let all = list.filter(|_| true);
+        assert!(all.ptr_eq(&list));
+        assert!(list.filter(|_| false).is_empty());
+    }
+
+    #[test]
+    fn reverse_and_append() {
+        let front: List<&str> = ["a", "b"].into_iter().collect();
+        let back: List<&str> = ["c", "d"].into_iter().collect();
+
+        let both = front.append(&back);
+        assert_eq!(both.iter().copied().collect::<String>(), "abcd");
+        assert!(both.tail().unwrap().tail().unwrap().ptr_eq(&back));
+        assert_eq!(both.len(), 4);
+
+        let reversed = both.reverse();
+        assert_eq!(reversed.iter().copied().collect::<String>(), "dcba");
+        assert_eq!(List::<&str>::new().append(&back), back);
+    }
+
+    #[test]
+    fn long_lists_drop_without_overflowing_the_stack() {
+        let list: List<u32> = (0..1_000_000).collect();
+        let shared = list.tail().unwrap();
+        drop(list);
+        assert_eq!(shared.len(), 999_999);
+        assert_eq!(shared.head(), Some(&1));
+    }
+
+    #[test]
+    fn dropping_a_list_keeps_shared_values_alive() {
+        let value = Rc::new(7);
+        let a: List<Rc<i32>> = List::new().push_front(Rc::clone(&value));
+        let b = a.push_front(Rc::clone(&value));
+        assert_eq!(Rc::strong_count(&value), 3);
+
+        drop(b);
+        assert_eq!(Rc::strong_count(&value), 2);
+        drop(a);
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn arc_lists_have_the_same_operations() {
+        assert_send_sync::<ArcList<String>>();
+
+        let list: ArcList<i32> = (1..=4).collect();
+        let doubled = list.map(|n| n * 2).reverse();
+        assert_eq!(doubled.iter().copied().collect::<Vec<_>>(), [8, 6, 4, 2]);
+        assert_eq!(list.filter(|n| n % 2 == 0).fold(0, |sum, n| sum + n), 6);
+        assert!(list.push_front(0).tail().unwrap().ptr_eq(&list));
+    }
+
+    /*
+        Like the counter in chapter 16, every thread gets its own clone of a shared
+        value, here the tail all the threads build their lists on.
+    */
+    #[test]
+    fn threads_share_one_tail() {
+        let tail: ArcList<u64> = (1..=1000).collect();
+        let mut handles = vec![];
+
+        for thread in 0..8 {
+            let tail = tail.clone();
+            let handle = thread::spawn(move || {
+                let list = tail.push_front(thread);
+                (list.fold(0, |sum, n| sum + n), list)
+            });
+            handles.push(handle);
+        }
+
+        for (thread, handle) in handles.into_iter().enumerate() {
+            let (sum, list) = handle.join().unwrap();
+            assert_eq!(sum, 500_500 + thread as u64);
+            assert!(list.tail().unwrap().ptr_eq(&tail));
+        }
+    }
+
+    #[test]
+    fn arc_lists_can_be_dropped_on_any_thread() {
+        let list: ArcList<u32> = (0..1_000_000).collect();
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let list = list.push_front(i);
+                thread::spawn(move || drop(list))
+            })
+            .collect();
+        drop(list);
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}
@@ -22,17 +22,135 @@
     use Mutex<T> instead.
 */
 
+pub mod messengers;
+pub mod mock;
+pub mod quota;
+pub mod shared;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Urgent,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Warning => "warning",
+            Severity::Urgent => "urgent",
+            Severity::Error => "error",
+        };
+        write!(f, "{name}")
+    }
+}
+
 pub trait Messenger {
     /*
         Accepts an immutable reference to self.
     */
     fn send(&self, msg: &str);
+
+    /*
+        Sends a message along with how serious it is. Messengers that can make use of
+        the severity, to pick a log level for example, override this; the rest just
+        send the message.
+    */
+    fn notify(&self, _severity: Severity, msg: &str) {
+        self.send(msg);
+    }
+}
+
+/*
+    A messenger shared through an Rc is still a messenger. This lets one messenger be
+    handed to another that owns its messengers, like FanOutMessenger, and still be
+    used from elsewhere.
+*/
+impl<M: Messenger + ?Sized> Messenger for Rc<M> {
+    fn send(&self, msg: &str) {
+        (**self).send(msg);
+    }
+
+    fn notify(&self, severity: Severity, msg: &str) {
+        (**self).notify(severity, msg);
+    }
+}
+
+/*
+    A level of use, in percent of the maximum, and the message sent when the value
+    reaches it. The template can refer to the current numbers with placeholders:
+    {value}, {max}, {percent} (the value as a percentage of max), {threshold}
+    (the percent of this threshold) and {severity}.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Threshold {
+    pub percent: u32,
+    pub severity: Severity,
+    pub template: String,
+}
+
+impl Threshold {
+    pub fn new(percent: u32, severity: Severity, template: &str) -> Threshold {
+        Threshold {
+            percent,
+            severity,
+            template: String::from(template),
+        }
+    }
+
+    /*
+        Compares value / max with percent / 100 using integers, so there is no rounding.
+        With a max of 0 that comparison holds for any value, 0 included, so nothing used
+        would already be over the quota; there, any use at all reaches every threshold.
+    */
+    fn reached_by(&self, value: usize, max: usize) -> bool {
+        if max == 0 {
+            return value > 0;
+        }
+        value as u128 * 100 >= max as u128 * u128::from(self.percent)
+    }
+
+    fn message(&self, value: usize, max: usize) -> String {
+        let percent = match max {
+            0 => String::from("100"),
+            max => (value as u128 * 100 / max as u128).to_string(),
+        };
+        self.template
+            .replace("{value}", &value.to_string())
+            .replace("{max}", &max.to_string())
+            .replace("{percent}", &percent)
+            .replace("{threshold}", &self.percent.to_string())
+            .replace("{severity}", &self.severity.to_string())
+    }
+}
+
+/*
+    The thresholds LimitTracker::new uses, with the messages it always sent.
+*/
+pub fn default_thresholds() -> Vec<Threshold> {
+    vec![
+        Threshold::new(
+            75,
+            Severity::Warning,
+            "Warning: You've used up over 75% of your quota!",
+        ),
+        Threshold::new(
+            90,
+            Severity::Urgent,
+            "Urgent warning: You've used up over 90% of your quota!",
+        ),
+        Threshold::new(100, Severity::Error, "Error: You are over your quota!"),
+    ]
 }
 
 pub struct LimitTracker<'a, T: Messenger> {
     messenger: &'a T,
     value: usize,
     max: usize,
+    thresholds: Vec<Threshold>,
+    notified: Vec<bool>,
 }
 
 impl<'a, T> LimitTracker<'a, T>
@@ -40,32 +158,83 @@ where
     T: Messenger,
 {
     pub fn new(messenger: &'a T, max: usize) -> LimitTracker<'a, T> {
+        LimitTracker::with_thresholds(messenger, max, default_thresholds())
+    }
+
+    /*
+        The thresholds can be given in any order; they are kept sorted by percent.
+    */
+    pub fn with_thresholds(
+        messenger: &'a T,
+        max: usize,
+        mut thresholds: Vec<Threshold>,
+    ) -> LimitTracker<'a, T> {
+        thresholds.sort_by_key(|threshold| threshold.percent);
         LimitTracker {
             messenger,
             value: 0,
             max,
+            notified: vec![false; thresholds.len()],
+            thresholds,
         }
     }
 
+    pub fn value(&self) -> usize {
+        self.value
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub fn thresholds(&self) -> &[Threshold] {
+        &self.thresholds
+    }
+
     pub fn set_value(&mut self, value: usize) {
         self.value = value;
 
-        let percentage_of_max = self.value as f64 / self.max as f64;
+        if let Some(threshold) = escalate(&self.thresholds, &mut self.notified, value, self.max) {
+            let message = threshold.message(self.value, self.max);
+            self.messenger.notify(threshold.severity, &message);
+        }
+    }
+}
 
-        if percentage_of_max >= 1.0 {
-            self.messenger.send("Error: You are over your quota!");
-        } else if percentage_of_max >= 0.9 {
-            self.messenger
-                .send("Urgent warning: You've used up over 90% of your quota!");
-        } else if percentage_of_max >= 0.75 {
-            self.messenger
-                .send("Warning: You've used up over 75% of your quota!");
+/*
+    Decides which threshold to send a message for after the value changed. Only the
+    highest threshold the value reaches counts, and only if it wasn't notified already.
+    A threshold is armed again once the value drops below it, so going back up sends
+    its message again. Going down from one threshold to a lower one that was passed
+    on the way up sends nothing.
+
+    The thresholds must be sorted by percent; notified holds a flag for each of them.
+*/
+fn escalate<'t>(
+    thresholds: &'t [Threshold],
+    notified: &mut [bool],
+    value: usize,
+    max: usize,
+) -> Option<&'t Threshold> {
+    let mut highest = None;
+    for (i, threshold) in thresholds.iter().enumerate() {
+        if threshold.reached_by(value, max) {
+            highest = Some(i);
+        } else {
+            notified[i] = false;
         }
     }
+
+    let i = highest.filter(|&i| !notified[i])?;
+    for flag in notified[..=i].iter_mut() {
+        *flag = true;
+    }
+    Some(&thresholds[i])
 }
 
 #[cfg(test)]
 mod tests {
+    use super::mock::RecordingMessenger;
     use super::*;
     use std::cell::RefCell;
 
@@ -114,6 +283,106 @@ mod tests {
 
         assert_eq!(mock_messenger.sent_messages.borrow().len(), 1);
     }
+
+    #[test]
+    fn it_sends_only_the_highest_threshold_reached() {
+        let mock_messenger = MockMessenger::new();
+        let mut limit_tracker = LimitTracker::new(&mock_messenger, 100);
+
+        limit_tracker.set_value(100);
+
+        assert_eq!(
+            *mock_messenger.sent_messages.borrow(),
+            ["Error: You are over your quota!"]
+        );
+    }
+
+    #[test]
+    fn it_does_not_repeat_a_message_until_the_value_drops_below_it() {
+        let mock_messenger = MockMessenger::new();
+        let mut limit_tracker = LimitTracker::new(&mock_messenger, 100);
+
+        for value in [80, 85, 92, 95, 91, 80, 60, 76] {
+            limit_tracker.set_value(value);
+        }
+
+        assert_eq!(
+            *mock_messenger.sent_messages.borrow(),
+            [
+                "Warning: You've used up over 75% of your quota!",
+                "Urgent warning: You've used up over 90% of your quota!",
+                "Warning: You've used up over 75% of your quota!",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_fills_in_message_templates() {
+        let mock_messenger = MockMessenger::new();
+        let thresholds = vec![
+            Threshold::new(
+                150,
+                Severity::Error,
+                "{severity}: {value} of {max} ({percent}%)",
+            ),
+            Threshold::new(
+                50,
+                Severity::Warning,
+                "{severity}: past {threshold}%, at {percent}%",
+            ),
+        ];
+        let mut limit_tracker = LimitTracker::with_thresholds(&mock_messenger, 40, thresholds);
+        assert_eq!(limit_tracker.thresholds()[0].percent, 50);
+
+        limit_tracker.set_value(19);
+        limit_tracker.set_value(21);
+        limit_tracker.set_value(61);
+
+        assert_eq!(
+            *mock_messenger.sent_messages.borrow(),
+            ["warning: past 50%, at 52%", "error: 61 of 40 (152%)"]
+        );
+    }
+
+    #[test]
+    fn a_max_of_zero_is_only_reached_by_some_use() {
+        let mock_messenger = MockMessenger::new();
+        let mut limit_tracker = LimitTracker::new(&mock_messenger, 0);
+
+        limit_tracker.set_value(0);
+        assert!(mock_messenger.sent_messages.borrow().is_empty());
+
+        limit_tracker.set_value(1);
+        assert_eq!(
+            *mock_messenger.sent_messages.borrow(),
+            ["Error: You are over your quota!"]
+        );
+    }
+
+    #[test]
+    fn it_passes_the_severity_to_the_messenger() {
+        let messenger = RecordingMessenger::new();
+        let mut limit_tracker = LimitTracker::new(&messenger, 10);
+
+        limit_tracker.set_value(8);
+        limit_tracker.set_value(9);
+        limit_tracker.set_value(12);
+
+        let severities: Vec<_> = messenger
+            .recorder()
+            .calls()
+            .into_iter()
+            .map(|call| call.severity)
+            .collect();
+        assert_eq!(
+            severities,
+            [
+                Some(Severity::Warning),
+                Some(Severity::Urgent),
+                Some(Severity::Error)
+            ]
+        );
+    }
 }
 
 /*
@@ -122,16 +391,17 @@ mod tests {
 */
 
 #[derive(Debug)]
+#[allow(dead_code)]
 enum List {
     Cons(Rc<RefCell<i32>>, Rc<List>),
     Nil,
 }
 
-use crate::List::{Cons, Nil};
+use self::List::{Cons, Nil};
 use std::cell::RefCell;
 use std::rc::Rc;
 
-fn main() {
+pub fn main() {
     let value = Rc::new(RefCell::new(5));
 
     let a = Rc::new(Cons(Rc::clone(&value), Rc::new(Nil)));
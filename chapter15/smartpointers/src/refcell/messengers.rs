@@ -0,0 +1,560 @@
+/*
+    Messengers that deliver LimitTracker warnings somewhere real:
+
+    - FileMessenger appends each message as a line to a file.
+    - SyslogMessenger formats messages as RFC 5424 syslog lines and sends them to a
+      local syslog socket, such as /dev/log.
+    - WebhookMessenger POSTs each message as JSON to an http:// URL.
+    - FanOutMessenger sends every message to several other messengers.
+
+    Messenger::send has no way to report an error, and a failing warning shouldn't
+    break the code that tracks the limit. So each messenger also has a try_notify
+    method that returns the io::Error, and its Messenger impl prints failures to
+    stderr instead.
+*/
+
+use super::{Messenger, Severity};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+fn report(result: io::Result<()>) {
+    if let Err(error) = result {
+        eprintln!("failed to deliver a message: {error}");
+    }
+}
+
+pub struct FileMessenger {
+    path: PathBuf,
+    file: File,
+}
+
+impl FileMessenger {
+    /*
+        Opens the file for appending, creating it if it doesn't exist yet.
+    */
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileMessenger> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(FileMessenger { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /*
+        Writes lines like "2023-11-14T22:13:20.000Z warning Quota almost used up".
+        The whole line goes out in a single write; in append mode the OS puts each
+        write at the end of the file, so lines from several processes don't mix.
+        Writing only needs &File, which is why send can take &self.
+    */
+    pub fn try_notify(&self, severity: Severity, msg: &str) -> io::Result<()> {
+        let line = format!("{} {severity} {msg}\n", rfc3339(SystemTime::now()));
+        (&self.file).write_all(line.as_bytes())
+    }
+}
+
+impl Messenger for FileMessenger {
+    fn send(&self, msg: &str) {
+        self.notify(Severity::Warning, msg);
+    }
+
+    fn notify(&self, severity: Severity, msg: &str) {
+        report(self.try_notify(severity, msg));
+    }
+}
+
+/*
+    An RFC 5424 line looks like this:
+
+        <PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG
+
+    PRI combines the facility (the kind of program that logs) and the severity as
+    facility * 8 + severity. Our severities map to warning (4), error (3) and
+    critical (2). A dash stands for a field without a value.
+*/
+#[cfg(unix)]
+pub struct SyslogMessenger {
+    socket: UnixDatagram,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+}
+
+#[cfg(unix)]
+impl SyslogMessenger {
+    pub const USER: u8 = 1;
+    pub const LOCAL0: u8 = 16;
+
+    /*
+        Connects to a local syslog socket. Most Linux systems listen on /dev/log.
+    */
+    pub fn connect<P: AsRef<Path>>(path: P, app_name: &str) -> io::Result<SyslogMessenger> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(SyslogMessenger {
+            socket,
+            facility: SyslogMessenger::USER,
+            hostname: String::from("-"),
+            app_name: header_field(app_name, 48),
+        })
+    }
+
+    /*
+        Facilities go from 0 (kernel) to 23 (local7).
+    */
+    pub fn with_facility(mut self, facility: u8) -> SyslogMessenger {
+        if facility > 23 {
+            panic!("Syslog facilities go from 0 to 23, got {facility}.");
+        }
+        self.facility = facility;
+        self
+    }
+
+    pub fn with_hostname(mut self, hostname: &str) -> SyslogMessenger {
+        self.hostname = header_field(hostname, 255);
+        self
+    }
+
+    pub fn format(&self, severity: Severity, msg: &str, time: SystemTime) -> String {
+        let level = match severity {
+            Severity::Warning => 4,
+            Severity::Urgent => 3,
+            Severity::Error => 2,
+        };
+        format!(
+            "<{}>1 {} {} {} {} quota - {msg}",
+            u32::from(self.facility) * 8 + level,
+            rfc3339(time),
+            self.hostname,
+            self.app_name,
+            process::id(),
+        )
+    }
+
+    pub fn try_notify(&self, severity: Severity, msg: &str) -> io::Result<()> {
+        let line = self.format(severity, msg, SystemTime::now());
+        self.socket.send(line.as_bytes()).map(|_| ())
+    }
+}
+
+#[cfg(unix)]
+impl Messenger for SyslogMessenger {
+    fn send(&self, msg: &str) {
+        self.notify(Severity::Warning, msg);
+    }
+
+    fn notify(&self, severity: Severity, msg: &str) {
+        report(self.try_notify(severity, msg));
+    }
+}
+
+/*
+    Header fields may only contain printable ASCII without spaces, up to a maximum
+    length. Anything else is dropped, and an empty field becomes the dash.
+*/
+fn header_field(value: &str, max_len: usize) -> String {
+    let field: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_len)
+        .collect();
+    if field.is_empty() {
+        String::from("-")
+    } else {
+        field
+    }
+}
+
+/*
+    Formats a time as an RFC 3339 timestamp in UTC, with milliseconds.
+*/
+pub fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let second_of_day = seconds % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        second_of_day / 3600,
+        second_of_day % 3600 / 60,
+        second_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/*
+    Turns a count of days since 1970-01-01 into a date. This is Howard Hinnant's
+    algorithm: it counts in 400 year eras, which repeat exactly, and starts years in
+    March so that the leap day falls at the end of the year.
+*/
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+/*
+    Sends each message as the body of a POST request:
+
+        {"severity":"warning","message":"Warning: You've used up over 75% of your quota!"}
+
+    Only plain http:// URLs are supported, since the standard library has no TLS.
+    An IPv6 address goes in brackets, as in http://[::1]:8080/hook, so that its
+    colons aren't taken for the port. Any 2xx status counts as delivered.
+*/
+pub struct WebhookMessenger {
+    host: String,
+    port: u16,
+    path: String,
+    timeout: Duration,
+}
+
+impl WebhookMessenger {
+    pub fn new(url: &str) -> io::Result<WebhookMessenger> {
+        let invalid = |reason| io::Error::new(io::ErrorKind::InvalidInput, reason);
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| invalid("webhook URLs must start with http://"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => bracketed
+                .split_once(']')
+                .ok_or_else(|| invalid("unclosed bracket in webhook URL"))?,
+            None => match authority.find(':') {
+                Some(colon) => authority.split_at(colon),
+                None => (authority, ""),
+            },
+        };
+        let port = match port {
+            "" => 80,
+            port => port
+                .strip_prefix(':')
+                .and_then(|port| port.parse().ok())
+                .ok_or_else(|| invalid("invalid port in webhook URL"))?,
+        };
+        if host.is_empty() {
+            return Err(invalid("webhook URL without a host"));
+        }
+        Ok(WebhookMessenger {
+            host: String::from(host),
+            port,
+            path: String::from(path),
+            timeout: Duration::from_secs(5),
+        })
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> WebhookMessenger {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn try_notify(&self, severity: Severity, msg: &str) -> io::Result<()> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other("webhook host has no address"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        // The Host header needs the brackets back around an IPv6 address.
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let body = format!(
+            "{{\"severity\":\"{severity}\",\"message\":\"{}\"}}",
+            escape_json(msg)
+        );
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.path,
+            host,
+            self.port,
+            body.len()
+        )?;
+        stream.flush()?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        let status = status_line.split_whitespace().nth(1).unwrap_or("");
+        match status.parse::<u16>() {
+            Ok(code) if (200..300).contains(&code) => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "webhook answered {:?}",
+                status_line.trim_end()
+            ))),
+        }
+    }
+}
+
+impl Messenger for WebhookMessenger {
+    fn send(&self, msg: &str) {
+        self.notify(Severity::Warning, msg);
+    }
+
+    fn notify(&self, severity: Severity, msg: &str) {
+        report(self.try_notify(severity, msg));
+    }
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/*
+    Holds its messengers as trait objects, so one fan-out can mix any kinds of them,
+    like the components of a Screen in chapter 17.
+*/
+#[derive(Default)]
+pub struct FanOutMessenger {
+    messengers: Vec<Box<dyn Messenger>>,
+}
+
+impl FanOutMessenger {
+    pub fn new() -> FanOutMessenger {
+        FanOutMessenger::default()
+    }
+
+    pub fn with<M: Messenger + 'static>(mut self, messenger: M) -> FanOutMessenger {
+        self.messengers.push(Box::new(messenger));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.messengers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messengers.is_empty()
+    }
+}
+
+impl Messenger for FanOutMessenger {
+    fn send(&self, msg: &str) {
+        for messenger in self.messengers.iter() {
+            messenger.send(msg);
+        }
+    }
+
+    fn notify(&self, severity: Severity, msg: &str) {
+        for messenger in self.messengers.iter() {
+            messenger.notify(severity, msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::{Call, RecordingMessenger};
+    use super::super::LimitTracker;
+    use super::*;
+    use std::fs;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("messengers-{}-{name}", process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn formats_rfc3339_timestamps() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        assert_eq!(rfc3339(time), "2023-11-14T22:13:20.123Z");
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(rfc3339(leap_day), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn file_messenger_appends_lines() {
+        let path = temp_path("file");
+        {
+            let messenger = FileMessenger::open(&path).unwrap();
+            let mut tracker = LimitTracker::new(&messenger, 10);
+            tracker.set_value(8);
+            tracker.set_value(10);
+        }
+        FileMessenger::open(&path).unwrap().send("reopened");
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with(" warning Warning: You've used up over 75% of your quota!"));
+        assert!(lines[1].ends_with(" error Error: You are over your quota!"));
+        assert!(lines[2].ends_with(" warning reopened"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn syslog_messenger_sends_rfc5424_lines() {
+        let path = temp_path("syslog.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+        let messenger = SyslogMessenger::connect(&path, "quota tracker")
+            .unwrap()
+            .with_facility(SyslogMessenger::LOCAL0)
+            .with_hostname("web-1");
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            messenger.format(Severity::Error, "over quota", time),
+            format!(
+                "<130>1 2023-11-14T22:13:20.000Z web-1 quotatracker {} quota - over quota",
+                process::id()
+            )
+        );
+
+        let mut tracker = LimitTracker::new(&messenger, 100);
+        tracker.set_value(95);
+        let mut buffer = [0; 512];
+        let received = server.recv(&mut buffer).unwrap();
+        let line = String::from_utf8_lossy(&buffer[..received]);
+        assert!(line.starts_with("<131>1 "), "{line}");
+        assert!(line.ends_with(" quota - Urgent warning: You've used up over 90% of your quota!"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    /*
+        A stub HTTP server that answers each request with the given status and hands
+        the request bodies to the test.
+    */
+    fn stub_server(statuses: Vec<&'static str>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks/quota", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = header.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let body = String::from_utf8(body).unwrap();
+                sender.send(format!("{}{body}", request_line)).unwrap();
+                let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, receiver)
+    }
+
+    #[test]
+    fn webhook_messenger_posts_json() {
+        let (url, requests) = stub_server(vec!["204 No Content", "500 Internal Server Error"]);
+        let messenger = WebhookMessenger::new(&url).unwrap();
+
+        messenger
+            .try_notify(Severity::Urgent, "90% \"used\"\n")
+            .unwrap();
+        assert_eq!(
+            requests.recv().unwrap(),
+            "POST /hooks/quota HTTP/1.1\r\n{\"severity\":\"urgent\",\"message\":\"90% \\\"used\\\"\\n\"}"
+        );
+
+        let error = messenger.try_notify(Severity::Error, "full").unwrap_err();
+        assert!(error.to_string().contains("500"));
+    }
+
+    #[test]
+    fn webhook_urls_must_be_plain_http() {
+        assert!(WebhookMessenger::new("https://example.com/hook").is_err());
+        assert!(WebhookMessenger::new("http://:80/hook").is_err());
+        assert!(WebhookMessenger::new("http://example.com:http/").is_err());
+
+        assert!(WebhookMessenger::new("http://[::1/hook").is_err());
+        assert!(WebhookMessenger::new("http://[::1]8080/hook").is_err());
+        assert!(WebhookMessenger::new("http://::1:8080/hook").is_err());
+
+        let parts = |url| {
+            let messenger = WebhookMessenger::new(url).unwrap();
+            (messenger.host, messenger.port, messenger.path)
+        };
+        let owned = |host: &str, port, path: &str| (String::from(host), port, String::from(path));
+        assert_eq!(parts("http://example.com"), owned("example.com", 80, "/"));
+        assert_eq!(parts("http://[::1]:8080/hook"), owned("::1", 8080, "/hook"));
+        assert_eq!(parts("http://[fe80::1]/"), owned("fe80::1", 80, "/"));
+    }
+
+    #[test]
+    fn fan_out_reaches_every_messenger() {
+        let first = Rc::new(RecordingMessenger::new());
+        let second = Rc::new(RecordingMessenger::new());
+        let fan_out = FanOutMessenger::new()
+            .with(Rc::clone(&first))
+            .with(Rc::clone(&second));
+        assert_eq!(fan_out.len(), 2);
+
+        let mut tracker = LimitTracker::new(&fan_out, 4);
+        tracker.set_value(3);
+        fan_out.send("plain");
+
+        let expected = [
+            Call {
+                severity: Some(Severity::Warning),
+                message: String::from("Warning: You've used up over 75% of your quota!"),
+            },
+            Call {
+                severity: None,
+                message: String::from("plain"),
+            },
+        ];
+        assert_eq!(first.recorder().calls(), expected);
+        assert_eq!(second.recorder().calls(), expected);
+    }
+}
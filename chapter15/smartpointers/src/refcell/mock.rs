@@ -0,0 +1,507 @@
+/*
+    A reusable test double for traits like Messenger. Instead of writing a new mock
+    with its own RefCell<Vec<String>> for every test, a mock keeps a Recorder of its
+    calls, and the test states what it expected:
+
+        let messenger = RecordingMessenger::new();
+        let mut tracker = LimitTracker::new(&messenger, 100);
+        tracker.set_value(80);
+
+        messenger
+            .expect()
+            .times(1)
+            .then(Pattern::contains("75%"))
+            .verify();
+
+    verify panics with every call lined up against what was expected of it, marking
+    the ones that didn't match, rather than with the two whole lists side by side.
+
+    The calls are kept behind a Mutex rather than a RefCell, so the same mocks also
+    work with SharedLimitTracker and other code that needs a Send + Sync messenger.
+*/
+
+use super::{Messenger, Severity};
+use std::fmt;
+use std::sync::Mutex;
+
+/*
+    Records the calls made to a mock. C is whatever the mock needs to remember about
+    one call, usually its arguments.
+*/
+pub struct Recorder<C> {
+    calls: Mutex<Vec<C>>,
+}
+
+impl<C: Clone + fmt::Debug> Recorder<C> {
+    pub fn new() -> Recorder<C> {
+        Recorder {
+            calls: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn record(&self, call: C) {
+        self.calls.lock().unwrap().push(call);
+    }
+
+    pub fn calls(&self) -> Vec<C> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.calls.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.calls.lock().unwrap().clear();
+    }
+
+    pub fn expect(&self) -> Expectation<'_, C> {
+        Expectation {
+            recorder: self,
+            times: None,
+            matchers: vec![],
+        }
+    }
+}
+
+impl<C: Clone + fmt::Debug> Default for Recorder<C> {
+    fn default() -> Recorder<C> {
+        Recorder::new()
+    }
+}
+
+/*
+    Decides whether a recorded call is the one expected. The Display text describes
+    the expected call in failure messages.
+*/
+pub trait Matcher<C>: fmt::Display {
+    fn matches(&self, call: &C) -> bool;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    Any,
+    Exact(String),
+    Contains(String),
+    StartsWith(String),
+}
+
+impl Pattern {
+    pub fn exact(text: &str) -> Pattern {
+        Pattern::Exact(String::from(text))
+    }
+
+    pub fn contains(text: &str) -> Pattern {
+        Pattern::Contains(String::from(text))
+    }
+
+    pub fn starts_with(text: &str) -> Pattern {
+        Pattern::StartsWith(String::from(text))
+    }
+
+    pub fn with_severity(self, severity: Severity) -> SeverityPattern {
+        SeverityPattern {
+            severity,
+            pattern: self,
+        }
+    }
+
+    pub fn matches_text(&self, text: &str) -> bool {
+        match self {
+            Pattern::Any => true,
+            Pattern::Exact(expected) => text == expected,
+            Pattern::Contains(part) => text.contains(part.as_str()),
+            Pattern::StartsWith(start) => text.starts_with(start.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Any => write!(f, "anything"),
+            Pattern::Exact(expected) => write!(f, "exactly {expected:?}"),
+            Pattern::Contains(part) => write!(f, "containing {part:?}"),
+            Pattern::StartsWith(start) => write!(f, "starting with {start:?}"),
+        }
+    }
+}
+
+/*
+    A pattern matches any call that has some text to match against.
+*/
+impl<C: AsRef<str>> Matcher<C> for Pattern {
+    fn matches(&self, call: &C) -> bool {
+        self.matches_text(call.as_ref())
+    }
+}
+
+/*
+    What a test expects of the recorded calls: how many there are, and what each one
+    looks like, in the order they were made. Build one with Recorder::expect.
+*/
+pub struct Expectation<'r, C> {
+    recorder: &'r Recorder<C>,
+    times: Option<usize>,
+    matchers: Vec<Box<dyn Matcher<C> + 'r>>,
+}
+
+impl<'r, C: Clone + fmt::Debug> Expectation<'r, C> {
+    /*
+        Exactly this many calls. Without it, the number of matchers given with then
+        is the number of calls expected, or any number if there are none.
+    */
+    pub fn times(mut self, times: usize) -> Expectation<'r, C> {
+        self.times = Some(times);
+        self
+    }
+
+    pub fn never(self) -> Expectation<'r, C> {
+        self.times(0)
+    }
+
+    /*
+        The next call must match matcher. With times, the matchers may cover only
+        the first few of the calls.
+    */
+    pub fn then(mut self, matcher: impl Matcher<C> + 'r) -> Expectation<'r, C> {
+        self.matchers.push(Box::new(matcher));
+        self
+    }
+
+    pub fn check(&self) -> Result<(), Mismatch> {
+        let calls = self.recorder.calls();
+        let expected = self.times.or(if self.matchers.is_empty() {
+            None
+        } else {
+            Some(self.matchers.len())
+        });
+
+        let mut lines = vec![];
+        for i in 0..calls.len().max(self.matchers.len()) {
+            let call = calls.get(i);
+            let matcher = self.matchers.get(i);
+            let counted = expected.is_none_or(|times| i < times);
+            let line = match (call, matcher) {
+                (Some(call), Some(matcher)) if matcher.matches(call) => Line::Expected {
+                    call: format!("{call:?}"),
+                },
+                (Some(call), None) if counted => Line::Expected {
+                    call: format!("{call:?}"),
+                },
+                _ => Line::Unexpected {
+                    expected: matcher.map(|matcher| matcher.to_string()),
+                    call: call.map(|call| format!("{call:?}")),
+                },
+            };
+            lines.push(line);
+        }
+
+        let wrong_count = expected.is_some_and(|times| times != calls.len());
+        let failed = wrong_count
+            || lines
+                .iter()
+                .any(|line| matches!(line, Line::Unexpected { .. }));
+        if failed {
+            Err(Mismatch {
+                expected,
+                actual: calls.len(),
+                lines,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /*
+        Panics with the differences if the calls don't meet the expectation.
+    */
+    pub fn verify(self) {
+        if let Err(mismatch) = self.check() {
+            panic!("{mismatch}");
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Expected {
+        call: String,
+    },
+    Unexpected {
+        expected: Option<String>,
+        call: Option<String>,
+    },
+}
+
+/*
+    How the calls differed from the expectation, one line per call, shown like this:
+
+        the calls didn't match: expected 2 calls, got 2
+           0   "first message"
+           1 - containing "90%"
+           1 + "Warning: ..."
+
+    Calls as expected are shown as they are, for context. For the others, - is what
+    was expected and + is the call that was made instead.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub expected: Option<usize>,
+    pub actual: usize,
+    pub lines: Vec<Line>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "call" } else { "calls" };
+        match self.expected {
+            Some(expected) => writeln!(
+                f,
+                "the calls didn't match: expected {expected} {}, got {}",
+                plural(expected),
+                self.actual
+            )?,
+            None => writeln!(f, "the calls didn't match: got {}", self.actual)?,
+        }
+        for (i, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Expected { call } => writeln!(f, "{i:>4}   {call}")?,
+                Line::Unexpected { expected, call } => {
+                    let expected = expected.as_deref().unwrap_or("no call");
+                    let call = call.as_deref().unwrap_or("no call");
+                    writeln!(f, "{i:>4} - {expected}")?;
+                    writeln!(f, "{i:>4} + {call}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+    One call to a Messenger. Calls through send have no severity.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub severity: Option<Severity>,
+    pub message: String,
+}
+
+impl AsRef<str> for Call {
+    fn as_ref(&self) -> &str {
+        &self.message
+    }
+}
+
+/*
+    Matches a call with the given severity and a message matching the pattern. Made
+    with Pattern::with_severity.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeverityPattern {
+    pub severity: Severity,
+    pub pattern: Pattern,
+}
+
+impl Matcher<Call> for SeverityPattern {
+    fn matches(&self, call: &Call) -> bool {
+        call.severity == Some(self.severity) && self.pattern.matches_text(&call.message)
+    }
+}
+
+impl fmt::Display for SeverityPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.severity, self.pattern)
+    }
+}
+
+/*
+    A Messenger that records everything sent through it.
+*/
+pub struct RecordingMessenger {
+    recorder: Recorder<Call>,
+}
+
+impl RecordingMessenger {
+    pub fn new() -> RecordingMessenger {
+        RecordingMessenger {
+            recorder: Recorder::new(),
+        }
+    }
+
+    pub fn recorder(&self) -> &Recorder<Call> {
+        &self.recorder
+    }
+
+    pub fn messages(&self) -> Vec<String> {
+        let calls = self.recorder.calls();
+        calls.into_iter().map(|call| call.message).collect()
+    }
+
+    pub fn expect(&self) -> Expectation<'_, Call> {
+        self.recorder.expect()
+    }
+}
+
+impl Default for RecordingMessenger {
+    fn default() -> RecordingMessenger {
+        RecordingMessenger::new()
+    }
+}
+
+impl Messenger for RecordingMessenger {
+    fn send(&self, msg: &str) {
+        self.recorder.record(Call {
+            severity: None,
+            message: String::from(msg),
+        });
+    }
+
+    fn notify(&self, severity: Severity, msg: &str) {
+        self.recorder.record(Call {
+            severity: Some(severity),
+            message: String::from(msg),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::shared::SharedLimitTracker;
+    use super::super::LimitTracker;
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn it_records_what_limit_tracker_sends() {
+        let messenger = RecordingMessenger::new();
+        let mut tracker = LimitTracker::new(&messenger, 100);
+
+        for value in [80, 95, 50] {
+            tracker.set_value(value);
+        }
+
+        messenger
+            .expect()
+            .then(Pattern::contains("75%").with_severity(Severity::Warning))
+            .then(Pattern::starts_with("Urgent warning").with_severity(Severity::Urgent))
+            .verify();
+        assert_eq!(messenger.messages().len(), 2);
+    }
+
+    #[test]
+    fn times_alone_only_counts_the_calls() {
+        let messenger = RecordingMessenger::new();
+        messenger.expect().never().verify();
+
+        messenger.send("one");
+        messenger.send("two");
+        messenger.expect().times(2).verify();
+        messenger.expect().verify();
+
+        messenger.recorder().clear();
+        assert!(messenger.recorder().is_empty());
+    }
+
+    #[test]
+    fn a_wrong_call_is_shown_against_what_was_expected() {
+        let messenger = RecordingMessenger::new();
+        messenger.notify(Severity::Warning, "Warning: 80%");
+        messenger.notify(Severity::Warning, "Warning: 92%");
+
+        let mismatch = messenger
+            .expect()
+            .then(Pattern::contains("80%"))
+            .then(Pattern::contains("92%").with_severity(Severity::Urgent))
+            .check()
+            .unwrap_err();
+
+        let call = "Call { severity: Some(Warning), message: \"Warning: 92%\" }";
+        assert_eq!(
+            mismatch.to_string(),
+            format!(
+                "the calls didn't match: expected 2 calls, got 2\n\
+                 \x20  0   Call {{ severity: Some(Warning), message: \"Warning: 80%\" }}\n\
+                 \x20  1 - urgent containing \"92%\"\n\
+                 \x20  1 + {call}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn missing_and_extra_calls_are_reported() {
+        let recorder: Recorder<String> = Recorder::new();
+        recorder.record(String::from("only"));
+
+        let missing = recorder
+            .expect()
+            .then(Pattern::Any)
+            .then(Pattern::exact("second"))
+            .check()
+            .unwrap_err();
+        assert_eq!(missing.actual, 1);
+        assert_eq!(
+            missing.lines[1],
+            Line::Unexpected {
+                expected: Some(String::from("exactly \"second\"")),
+                call: None,
+            }
+        );
+
+        recorder.record(String::from("extra"));
+        let extra = recorder.expect().then(Pattern::Any).check().unwrap_err();
+        assert_eq!(extra.expected, Some(1));
+        assert!(extra
+            .to_string()
+            .contains("   1 - no call\n   1 + \"extra\"\n"));
+
+        // With a count, the matchers only need to cover the first calls.
+        recorder
+            .expect()
+            .times(2)
+            .then(Pattern::exact("only"))
+            .verify();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected 3 calls, got 1")]
+    fn verify_panics_with_the_differences() {
+        let messenger = RecordingMessenger::new();
+        messenger.send("hello");
+        messenger.expect().times(3).verify();
+    }
+
+    #[test]
+    fn it_works_with_the_shared_tracker() {
+        let messenger = Arc::new(RecordingMessenger::new());
+        let tracker = Arc::new(SharedLimitTracker::new(Arc::clone(&messenger), 10));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let tracker = Arc::clone(&tracker);
+                thread::spawn(move || {
+                    for _ in 0..5 {
+                        tracker.add(1);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // Messages are sent after the lock is released, so a warning may arrive last.
+        let calls = messenger.recorder().calls();
+        let errors = calls
+            .iter()
+            .filter(|call| call.severity == Some(Severity::Error))
+            .count();
+        assert_eq!(errors, 1);
+    }
+}
@@ -0,0 +1,329 @@
+/*
+    Tracks a quota for every key (a user name or an API key, say) instead of the single
+    value of a LimitTracker. Usage doesn't only go up: depending on the policy it goes
+    back to zero at the end of each window, or drains away continuously like water
+    leaking out of a bucket.
+
+    The same thresholds as LimitTracker apply to each key separately, and the message
+    templates can also use {key}, so the warnings say which key crossed which threshold.
+*/
+
+use super::{escalate, Messenger, Severity, Threshold};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Period {
+    pub fn duration(&self) -> Duration {
+        match self {
+            Period::Minute => Duration::from_secs(60),
+            Period::Hour => Duration::from_secs(60 * 60),
+            Period::Day => Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /*
+        Usage starts again from zero every period. Each key's windows start when the
+        key is first used, not on the hour of the clock.
+    */
+    FixedWindow(Period),
+    /*
+        Usage drains at a steady rate, so a key that stays under the rate never runs
+        out, while bursts are allowed up to the maximum.
+    */
+    TokenBucket { drain_per_second: f64 },
+}
+
+pub fn default_quota_thresholds() -> Vec<Threshold> {
+    vec![
+        Threshold::new(
+            75,
+            Severity::Warning,
+            "Warning: {key} has used {percent}% of its quota (over {threshold}%)",
+        ),
+        Threshold::new(
+            90,
+            Severity::Urgent,
+            "Urgent warning: {key} has used {percent}% of its quota (over {threshold}%)",
+        ),
+        Threshold::new(100, Severity::Error, "Error: {key} is over its quota!"),
+    ]
+}
+
+struct KeyUsage {
+    used: f64,
+    window_start: Instant,
+    last_update: Instant,
+    notified: Vec<bool>,
+}
+
+impl KeyUsage {
+    /*
+        Brings the usage up to date: starts a new window if the current one is over,
+        or drains the bucket for the time that went by. A new window also starts with
+        no warnings sent, so every window warns again.
+    */
+    fn refresh(&mut self, policy: Policy, now: Instant) {
+        match policy {
+            Policy::FixedWindow(period) => {
+                let length = period.duration();
+                let elapsed = now.saturating_duration_since(self.window_start);
+                if elapsed >= length {
+                    // The current window began as long ago as we are into it.
+                    let into_window = elapsed.as_nanos() % length.as_nanos();
+                    let into_window = u64::try_from(into_window)
+                        .expect("a window is shorter than u64::MAX nanoseconds");
+                    self.window_start = now - Duration::from_nanos(into_window);
+                    self.used = 0.0;
+                    self.notified.fill(false);
+                }
+            }
+            Policy::TokenBucket { drain_per_second } => {
+                let elapsed = now.saturating_duration_since(self.last_update);
+                self.used = (self.used - drain_per_second * elapsed.as_secs_f64()).max(0.0);
+            }
+        }
+        self.last_update = self.last_update.max(now);
+    }
+}
+
+pub struct QuotaTracker<'a, T: Messenger> {
+    messenger: &'a T,
+    max: usize,
+    policy: Policy,
+    thresholds: Vec<Threshold>,
+    keys: HashMap<String, KeyUsage>,
+}
+
+impl<'a, T> QuotaTracker<'a, T>
+where
+    T: Messenger,
+{
+    pub fn new(messenger: &'a T, max: usize, policy: Policy) -> QuotaTracker<'a, T> {
+        QuotaTracker::with_thresholds(messenger, max, policy, default_quota_thresholds())
+    }
+
+    pub fn with_thresholds(
+        messenger: &'a T,
+        max: usize,
+        policy: Policy,
+        mut thresholds: Vec<Threshold>,
+    ) -> QuotaTracker<'a, T> {
+        if let Policy::TokenBucket { drain_per_second } = policy {
+            if !(drain_per_second >= 0.0 && drain_per_second.is_finite()) {
+                panic!(
+                    "The drain rate must be a finite rate of at least 0, got {drain_per_second}."
+                );
+            }
+        }
+        thresholds.sort_by_key(|threshold| threshold.percent);
+        QuotaTracker {
+            messenger,
+            max,
+            policy,
+            thresholds,
+            keys: HashMap::new(),
+        }
+    }
+
+    /*
+        Adds amount to the key's usage and returns the usage afterwards, sending a
+        warning if that crosses a threshold.
+    */
+    pub fn record(&mut self, key: &str, amount: usize) -> usize {
+        self.record_at(key, amount, Instant::now())
+    }
+
+    pub fn record_at(&mut self, key: &str, amount: usize, now: Instant) -> usize {
+        let thresholds = self.thresholds.len();
+        let usage = self
+            .keys
+            .entry(String::from(key))
+            .or_insert_with(|| KeyUsage {
+                used: 0.0,
+                window_start: now,
+                last_update: now,
+                notified: vec![false; thresholds],
+            });
+        usage.refresh(self.policy, now);
+        usage.used += amount as f64;
+
+        let used = usage.used as usize;
+        if let Some(threshold) = escalate(&self.thresholds, &mut usage.notified, used, self.max) {
+            let message = threshold.message(used, self.max).replace("{key}", key);
+            self.messenger.notify(threshold.severity, &message);
+        }
+        used
+    }
+
+    /*
+        The key's usage at the given time, without recording anything.
+    */
+    pub fn usage_at(&self, key: &str, now: Instant) -> usize {
+        match self.keys.get(key) {
+            Some(usage) => {
+                let mut usage = KeyUsage {
+                    notified: Vec::new(),
+                    ..*usage
+                };
+                usage.refresh(self.policy, now);
+                usage.used as usize
+            }
+            None => 0,
+        }
+    }
+
+    pub fn usage(&self, key: &str) -> usize {
+        self.usage_at(key, Instant::now())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.keys.keys().map(|key| key.as_str())
+    }
+
+    /*
+        Forgets the keys whose usage is back to zero, so a tracker that sees many
+        different keys doesn't keep all of them forever.
+    */
+    pub fn forget_idle(&mut self, now: Instant) {
+        let policy = self.policy;
+        self.keys.retain(|_, usage| {
+            usage.refresh(policy, now);
+            usage.used > 0.0
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::RecordingMessenger;
+    use super::*;
+
+    #[test]
+    fn keys_are_tracked_separately() {
+        let recorder = RecordingMessenger::new();
+        let mut tracker = QuotaTracker::new(&recorder, 10, Policy::FixedWindow(Period::Hour));
+        let now = Instant::now();
+
+        assert_eq!(tracker.record_at("alice", 8, now), 8);
+        assert_eq!(tracker.record_at("bob", 5, now), 5);
+        assert_eq!(tracker.record_at("bob", 4, now), 9);
+        assert_eq!(tracker.record_at("alice", 3, now), 11);
+
+        assert_eq!(
+            recorder.messages(),
+            [
+                "Warning: alice has used 80% of its quota (over 75%)",
+                "Urgent warning: bob has used 90% of its quota (over 90%)",
+                "Error: alice is over its quota!",
+            ]
+        );
+        let mut keys: Vec<&str> = tracker.keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["alice", "bob"]);
+    }
+
+    #[test]
+    fn fixed_windows_reset_usage_and_warnings() {
+        let recorder = RecordingMessenger::new();
+        let mut tracker = QuotaTracker::new(&recorder, 4, Policy::FixedWindow(Period::Minute));
+        let start = Instant::now();
+        let seconds = |s| start + Duration::from_secs(s);
+
+        tracker.record_at("key", 3, seconds(0));
+        assert_eq!(tracker.record_at("key", 1, seconds(59)), 4);
+        assert_eq!(tracker.usage_at("key", seconds(59)), 4);
+        assert_eq!(tracker.usage_at("key", seconds(60)), 0);
+
+        // The windows stay aligned to the first use: 120 to 180 is the third one.
+        assert_eq!(tracker.record_at("key", 3, seconds(150)), 3);
+        assert_eq!(tracker.record_at("key", 1, seconds(181)), 1);
+
+        assert_eq!(
+            recorder.messages(),
+            [
+                "Warning: key has used 75% of its quota (over 75%)",
+                "Error: key is over its quota!",
+                "Warning: key has used 75% of its quota (over 75%)",
+            ]
+        );
+    }
+
+    #[test]
+    fn windows_stay_aligned_after_a_very_long_gap() {
+        let recorder = RecordingMessenger::new();
+        let mut tracker = QuotaTracker::new(&recorder, 10, Policy::FixedWindow(Period::Minute));
+        let start = Instant::now();
+        // More windows than fit in a u32, and half a minute into the last one.
+        let later = start + Duration::from_secs(60 * (u64::from(u32::MAX) + 5) + 30);
+
+        tracker.record_at("key", 1, start);
+        assert_eq!(tracker.record_at("key", 2, later), 2);
+        assert_eq!(tracker.usage_at("key", later + Duration::from_secs(29)), 2);
+        assert_eq!(tracker.usage_at("key", later + Duration::from_secs(30)), 0);
+    }
+
+    #[test]
+    fn token_bucket_drains_over_time() {
+        let recorder = RecordingMessenger::new();
+        let policy = Policy::TokenBucket {
+            drain_per_second: 2.0,
+        };
+        let mut tracker = QuotaTracker::new(&recorder, 10, policy);
+        let start = Instant::now();
+        let millis = |ms| start + Duration::from_millis(ms);
+
+        tracker.record_at("api", 9, millis(0));
+        assert_eq!(tracker.usage_at("api", millis(1500)), 6);
+        assert_eq!(tracker.record_at("api", 1, millis(2000)), 6);
+        assert_eq!(tracker.record_at("api", 2, millis(2000)), 8);
+        assert_eq!(tracker.usage_at("api", millis(10_000)), 0);
+
+        assert_eq!(
+            recorder.messages(),
+            [
+                "Urgent warning: api has used 90% of its quota (over 90%)",
+                "Warning: api has used 80% of its quota (over 75%)",
+            ]
+        );
+    }
+
+    #[test]
+    fn idle_keys_are_forgotten() {
+        let recorder = RecordingMessenger::new();
+        let mut tracker = QuotaTracker::new(&recorder, 10, Policy::FixedWindow(Period::Day));
+        let start = Instant::now();
+        tracker.record_at("old", 1, start);
+        tracker.record_at("new", 1, start + Duration::from_secs(23 * 60 * 60));
+
+        tracker.forget_idle(start + Period::Day.duration());
+        assert_eq!(tracker.keys().collect::<Vec<_>>(), ["new"]);
+    }
+
+    #[test]
+    fn custom_thresholds_can_name_the_key() {
+        let recorder = RecordingMessenger::new();
+        let thresholds = vec![Threshold::new(
+            50,
+            Severity::Warning,
+            "{key}: {value}/{max}",
+        )];
+        let mut tracker = QuotaTracker::with_thresholds(
+            &recorder,
+            100,
+            Policy::FixedWindow(Period::Minute),
+            thresholds,
+        );
+        tracker.record("team-a", 60);
+        assert_eq!(recorder.messages(), ["team-a: 60/100"]);
+    }
+}
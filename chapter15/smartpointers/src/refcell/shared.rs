@@ -0,0 +1,235 @@
+/*
+    LimitTracker can't be shared between threads: set_value takes &mut self, and the
+    messenger is borrowed with a lifetime, so the tracker can't outlive the function
+    that made it. SharedLimitTracker is the thread-safe version, along the lines of
+    chapter 16's Arc<Mutex<T>> counter:
+
+    - the messenger is shared through an Arc and must be Send + Sync, so any thread
+      can send through it;
+    - the value is an AtomicUsize, so threads update it through &self;
+    - deciding which message to send needs a lock, a Mutex around the flags of the
+      thresholds already notified. Most updates can't change those flags, though:
+      after each check, the notified thresholds are exactly the ones the value
+      reaches. So an update that leaves the value reaching the same thresholds as at
+      the last check skips the lock, and only crossing a threshold takes it.
+    - the message is sent after the lock is released, so a slow messenger doesn't
+      hold up the other threads. Messages decided by different threads may therefore
+      arrive in a different order than they were decided in.
+
+    A messenger for it can't use RefCell to record messages, since RefCell isn't Sync;
+    a Mutex does the same job across threads, as RecordingMessenger does.
+*/
+
+use super::{default_thresholds, escalate, Messenger, Threshold};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub struct SharedLimitTracker<T: Messenger + Send + Sync> {
+    messenger: Arc<T>,
+    value: AtomicUsize,
+    max: usize,
+    thresholds: Vec<Threshold>,
+    notified: Mutex<Vec<bool>>,
+    level: AtomicUsize, // how many thresholds the value reached at the last check
+}
+
+impl<T> SharedLimitTracker<T>
+where
+    T: Messenger + Send + Sync,
+{
+    pub fn new(messenger: Arc<T>, max: usize) -> SharedLimitTracker<T> {
+        SharedLimitTracker::with_thresholds(messenger, max, default_thresholds())
+    }
+
+    pub fn with_thresholds(
+        messenger: Arc<T>,
+        max: usize,
+        mut thresholds: Vec<Threshold>,
+    ) -> SharedLimitTracker<T> {
+        thresholds.sort_by_key(|threshold| threshold.percent);
+        SharedLimitTracker {
+            messenger,
+            value: AtomicUsize::new(0),
+            max,
+            notified: Mutex::new(vec![false; thresholds.len()]),
+            level: AtomicUsize::new(0),
+            thresholds,
+        }
+    }
+
+    pub fn value(&self) -> usize {
+        self.value.load(Ordering::SeqCst)
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub fn thresholds(&self) -> &[Threshold] {
+        &self.thresholds
+    }
+
+    pub fn set_value(&self, value: usize) {
+        self.value.store(value, Ordering::SeqCst);
+        self.check();
+    }
+
+    /*
+        Adds amount to the value in one atomic step, so no other thread's update is
+        lost in between, and returns the new value.
+    */
+    pub fn add(&self, amount: usize) -> usize {
+        let value = self.value.fetch_add(amount, Ordering::SeqCst) + amount;
+        self.check();
+        value
+    }
+
+    fn level(&self, value: usize) -> usize {
+        self.thresholds
+            .iter()
+            .filter(|threshold| threshold.reached_by(value, self.max))
+            .count()
+    }
+
+    /*
+        By the time a thread gets the lock, other threads may have changed the value
+        again, so the value is read once more under the lock. Every check then works
+        from the latest value and no two threads send the same message. If other
+        threads carried the value past a threshold and on to the next one meanwhile,
+        only the higher one is sent, just as when set_value jumps past both.
+
+        A thread that skipped the lock may have done so just before the level was
+        updated, comparing against the old one. So after updating it, the check reads
+        the value again and goes round once more if its level has changed since.
+    */
+    fn check(&self) {
+        if self.level(self.value.load(Ordering::SeqCst)) == self.level.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let mut messages = Vec::new();
+        {
+            let mut notified = self.notified.lock().unwrap();
+            let mut value = self.value.load(Ordering::SeqCst);
+            loop {
+                if let Some(threshold) =
+                    escalate(&self.thresholds, &mut notified, value, self.max)
+                {
+                    messages.push((threshold.severity, threshold.message(value, self.max)));
+                }
+                let level = self.level(value);
+                self.level.store(level, Ordering::SeqCst);
+
+                value = self.value.load(Ordering::SeqCst);
+                if self.level(value) == level {
+                    break;
+                }
+            }
+        } // the lock is released here, before any message is sent
+
+        for (severity, message) in messages {
+            self.messenger.notify(severity, &message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::RecordingMessenger;
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn the_tracker_can_be_shared_between_threads() {
+        assert_send_sync::<SharedLimitTracker<RecordingMessenger>>();
+    }
+
+    #[test]
+    fn it_sends_the_same_messages_as_limit_tracker() {
+        let messenger = Arc::new(RecordingMessenger::new());
+        let tracker = SharedLimitTracker::new(Arc::clone(&messenger), 100);
+
+        for value in [80, 85, 92, 95, 91, 80, 60, 76] {
+            tracker.set_value(value);
+        }
+
+        assert_eq!(
+            messenger.messages(),
+            [
+                "Warning: You've used up over 75% of your quota!",
+                "Urgent warning: You've used up over 90% of your quota!",
+                "Warning: You've used up over 75% of your quota!",
+            ]
+        );
+    }
+
+    /*
+        Holds up every message until the test lets it go, and says when one arrives.
+    */
+    struct SlowMessenger {
+        arrived: mpsc::Sender<()>,
+        go: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl Messenger for SlowMessenger {
+        fn send(&self, _message: &str) {
+            self.arrived.send(()).unwrap();
+            self.go.lock().unwrap().recv().unwrap();
+        }
+    }
+
+    #[test]
+    fn no_lock_is_held_while_a_message_is_sent() {
+        let (arrived, arrivals) = mpsc::channel();
+        let (go, wait) = mpsc::channel();
+        let messenger = Arc::new(SlowMessenger {
+            arrived,
+            go: Mutex::new(wait),
+        });
+        let tracker = Arc::new(SharedLimitTracker::new(messenger, 100));
+
+        let sender = {
+            let tracker = Arc::clone(&tracker);
+            thread::spawn(move || tracker.set_value(80))
+        };
+        arrivals.recv().unwrap();
+
+        // Dropping below 75% changes the flags, so this needs the lock.
+        tracker.set_value(10);
+        go.send(()).unwrap();
+        sender.join().unwrap();
+        assert_eq!(tracker.value(), 10);
+    }
+
+    #[test]
+    fn concurrent_adds_are_all_counted_and_warned_once() {
+        let messenger = Arc::new(RecordingMessenger::new());
+        let tracker = Arc::new(SharedLimitTracker::new(Arc::clone(&messenger), 1000));
+        let mut handles = vec![];
+
+        for _ in 0..10 {
+            let tracker = Arc::clone(&tracker);
+            let handle = thread::spawn(move || {
+                for _ in 0..100 {
+                    tracker.add(1);
+                }
+            });
+            handles.push(handle);
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(tracker.value(), 1000);
+        // Threads send their messages unlocked, so they can arrive in any order.
+        let sent = messenger.messages();
+        assert!(sent.contains(&String::from("Error: You are over your quota!")));
+        for (i, message) in sent.iter().enumerate() {
+            assert!(!sent[..i].contains(message), "{message} was sent twice");
+        }
+    }
+}
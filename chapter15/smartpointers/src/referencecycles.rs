@@ -5,7 +5,7 @@
     the values will never be dropped.
 */
 
-use crate::List::{Cons, Nil};
+use self::List::{Cons, Nil};
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -31,7 +31,7 @@ impl List {
     }
 }
 
-fn main() {
+pub fn main() {
     let a = Rc::new(Cons(5, RefCell::new(Rc::new(Nil))));
 
     println!("a initial rc count = {}", Rc::strong_count(&a));
@@ -78,14 +78,10 @@ fn main() {
     with Some<Rc<T>> if the value still exists.
 */
 
-use std::cell::RefCell;
-use std::rc::Rc;
-
 /*
     We create a graph structure in which a node owns references to its children.
 */
-use std::cell::RefCell;
-use std::rc::{Rc, Weak};
+use std::rc::Weak;
 
 #[derive(Debug)]
 struct Node {
@@ -94,7 +90,7 @@ struct Node {
     children: RefCell<Vec<Rc<Node>>>,
 }
 
-fn main() {
+pub fn tree() {
     let leaf = Rc::new(Node {
         value: 3,
         parent: RefCell::new(Weak::new()),
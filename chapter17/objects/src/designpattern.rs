@@ -1,99 +1,484 @@
 /*
     We will go ahead and implement the object-oriented state pattern in Rust. This pattern 
     requires a set of states a value can have internally. The value's behavior changes based
-    on its state. The states are represented by a set of state objects in our
-    first approach.
+    on its state. The book represents the states by a set of state objects in its
+    first approach; here Post keeps its state in an enum generated by the workflow!
+    macro, from the same definition as the type states of the second approach.
 */
 
+use std::fmt;
+use std::io::{self, Write};
+use std::slice;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+    The store submodule saves posts to files. As a child module, it can see the private
+    fields of Post it needs to rebuild on load.
+*/
+pub mod store;
+
+/*
+    The workflow submodule holds the workflow! macro, which generates both the runtime
+    states of Post and the type-state version at the end of this file.
+*/
+pub mod workflow;
+
+use workflow::{workflow, WorkflowError};
+
+/*
+    The markdown submodule turns published content into sanitized HTML.
+*/
+pub mod markdown;
+
+/*
+    The server submodule exposes the workflow over HTTP.
+*/
+pub mod server;
+
+/*
+    By default a post needs sign-off from two different reviewers before it is published.
+*/
+const DEFAULT_REQUIRED_APPROVALS: usize = 2;
+
 pub struct Post { // has private fields
-    state: Option<Box<dyn State>>, // allows states of any type
+    state: PostState, // one of the states declared in the workflow below
     content: String,
+    author: String,
+    required_approvals: usize,
+    approvals: Vec<String>, // the reviewers who signed off in the current review
+    publish_at: Option<SystemTime>, // when an approved post goes public, if not right away
+    revisions: Vec<Revision>,
+    audit_log: Vec<Transition>,
+}
+
+/*
+    A record of one accepted workflow action. Post only ever pushes onto its
+    audit log and hands out shared references, so entries can't be changed or removed.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub action: &'static str,
+    pub from: &'static str,
+    pub to: &'static str,
+    pub actor: String,
+    pub timestamp: SystemTime,
+}
+
+impl Transition {
+    /*
+        Formats the transition as a single line JSON object, with the timestamp
+        in milliseconds since the Unix epoch.
+    */
+    pub fn to_json(&self) -> String {
+        let millis = to_millis(self.timestamp);
+        format!(
+            "{{\"action\":\"{}\",\"from\":\"{}\",\"to\":\"{}\",\"actor\":\"{}\",\"timestamp_ms\":{}}}",
+            self.action,
+            self.from,
+            self.to,
+            escape_json(&self.actor),
+            millis
+        )
+    }
+}
+
+/*
+    SystemTime can be before the epoch, where duration_since fails with the distance
+    the other way, so the millis are signed. An i128 holds the millis of any time a
+    SystemTime can hold, so every time can be written, in the audit log as in the store.
+*/
+fn to_millis(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_millis() as i128,
+        Err(before) => -(before.duration().as_millis() as i128),
+    }
+}
+
+/*
+    Every change to the content is stored as a full snapshot, numbered from 0
+    (the empty post). Snapshots make rolling back trivial at the cost of some memory.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Revision {
+    pub number: usize,
+    pub content: String,
+}
+
+/*
+    One line of a diff between two revisions.
+*/
+#[derive(Debug, PartialEq)]
+pub enum DiffLine<'a> {
+    Unchanged(&'a str),
+    Added(&'a str),
+    Removed(&'a str),
+}
+
+/*
+    The reasons an approval can be refused. Other actions only fail when the workflow
+    doesn't allow them, with a WorkflowError, but an approval can also be refused
+    because of who gives it.
+*/
+#[derive(Debug, PartialEq)]
+pub enum ApprovalError {
+    SelfApproval,
+    NotPending(&'static str),
+    DuplicateApproval(String),
+}
+
+impl fmt::Display for ApprovalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApprovalError::SelfApproval => write!(f, "authors cannot approve their own posts"),
+            ApprovalError::NotPending(state) => {
+                write!(f, "posts can only be approved while pending review, not while {state}")
+            }
+            ApprovalError::DuplicateApproval(reviewer) => {
+                write!(f, "{reviewer} has already approved this post")
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EditError {
+    NotDraft(&'static str),
+    NoSuchRevision(usize),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::NotDraft(state) => {
+                write!(f, "posts can only be edited as drafts, not while {state}")
+            }
+            EditError::NoSuchRevision(number) => write!(f, "revision {number} does not exist"),
+        }
+    }
+}
+
+/*
+    The states of a post and the actions that move it between them, declared once.
+    The workflow! macro turns this into PostState, the runtime machine Post keeps
+    its state in, and into the type states at the end of this file, so the two
+    versions can't drift apart.
+
+    A post stays in review until enough distinct reviewers approve it. A reviewer
+    may also pick a publication time, in which case the last approval makes the post
+    Scheduled (approve_scheduled) instead of Published. A scheduled post is accepted
+    but stays hidden until its time comes. It can't be approved again, since its
+    review is over; rejecting it sends it back to Draft. Archived posts are taken down from
+    the blog and leave no transition.
+*/
+workflow! {
+    machine: PostState,
+    typed: TypedPost,
+    markers: post_states,
+    data: String,
+    states: [
+        Draft = "draft",
+        PendingReview = "pending_review",
+        Scheduled = "scheduled",
+        Published = "published",
+        Archived = "archived",
+    ],
+    transitions: [
+        request_review: Draft => PendingReview,
+        reject: PendingReview => Draft,
+        approve: PendingReview => Published,
+        approve_scheduled: PendingReview => Scheduled,
+        reject: Scheduled => Draft,
+        archive: Scheduled => Archived,
+        archive: Published => Archived,
+    ],
+}
+
+/*
+    What each state allows besides its transitions. With the state objects each of
+    these was a method every state struct could override; with an enum, each one is
+    a match over the states instead.
+*/
+impl PostState {
+    fn allows_editing(self) -> bool {
+        self == PostState::Draft
+    }
+
+    fn is_published(self, publish_at: Option<SystemTime>) -> bool {
+        match self {
+            PostState::Published => true,
+            PostState::Scheduled => publish_at.is_some_and(|time| SystemTime::now() >= time),
+            _ => false,
+        }
+    }
 }
 
 impl Post {
-    pub fn new() -> Post {
+    pub fn new(author: &str) -> Post {
+        Post::with_required_approvals(author, DEFAULT_REQUIRED_APPROVALS)
+    }
+    pub fn with_required_approvals(author: &str, required_approvals: usize) -> Post {
         Post {
-            state: Some(Box::new(Draft {})),
+            state: PostState::INITIAL,
             content: String::new(),
+            author: String::from(author),
+            required_approvals,
+            approvals: Vec::new(),
+            publish_at: None,
+            revisions: vec![Revision {
+                number: 0,
+                content: String::new(),
+            }],
+            audit_log: Vec::new(),
         }
     }
-    pub fn add_text(&mut self, text: &str) {
+    pub fn add_text(&mut self, text: &str) -> Result<(), EditError> {
+        self.check_editable()?;
         self.content.push_str(text);
+        self.record_revision();
+        Ok(())
+    }
+    pub fn revisions(&self) -> &[Revision] {
+        &self.revisions
+    }
+    /*
+        Rolling back doesn't erase history; the restored content is recorded as a
+        new revision, so the rollback itself can be undone later.
+    */
+    pub fn rollback(&mut self, number: usize) -> Result<(), EditError> {
+        self.check_editable()?;
+        let content = self.revision(number)?.content.clone();
+        self.content = content;
+        self.record_revision();
+        Ok(())
+    }
+    pub fn diff(&self, from: usize, to: usize) -> Result<Vec<DiffLine<'_>>, EditError> {
+        let old = self.revision(from)?;
+        let new = self.revision(to)?;
+        Ok(diff_lines(&old.content, &new.content))
+    }
+    fn revision(&self, number: usize) -> Result<&Revision, EditError> {
+        self.revisions
+            .get(number)
+            .ok_or(EditError::NoSuchRevision(number))
+    }
+    fn check_editable(&self) -> Result<(), EditError> {
+        if self.state.allows_editing() {
+            Ok(())
+        } else {
+            Err(EditError::NotDraft(self.state.name()))
+        }
+    }
+    fn record_revision(&mut self) {
+        self.revisions.push(Revision {
+            number: self.revisions.len(),
+            content: self.content.clone(),
+        });
     }
     pub fn content(&self) -> &str {
+        if self.is_published() {
+            &self.content
+        } else {
+            ""
+        }
+    }
+    fn is_published(&self) -> bool {
+        self.state.is_published(self.publish_at)
+    }
+    /*
+        Renders the content as HTML, but only once the post is published;
+        before that there is nothing public to render, so we return None.
+    */
+    pub fn render_html(&self) -> Option<String> {
+        if self.is_published() {
+            Some(markdown::render(&self.content))
+        } else {
+            None
+        }
+    }
+    pub fn state(&self) -> PostState {
+        self.state
+    }
+    pub fn state_name(&self) -> &'static str {
+        self.state.name()
+    }
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+    /*
+        The approvals only count while the post is in review; once it leaves review
+        they are history, kept in the audit log.
+    */
+    pub fn approvals(&self) -> &[String] {
+        if self.state == PostState::PendingReview {
+            &self.approvals
+        } else {
+            &[]
+        }
+    }
+    pub fn publish_at(&self) -> Option<SystemTime> {
+        match self.state {
+            PostState::PendingReview | PostState::Scheduled => self.publish_at,
+            _ => None,
+        }
+    }
+    pub fn audit_log(&self) -> slice::Iter<'_, Transition> {
+        self.audit_log.iter()
+    }
+    /*
+        Writes the audit log in the JSON lines format: one JSON object per line.
+    */
+    pub fn write_audit_log<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for transition in self.audit_log() {
+            writeln!(out, "{}", transition.to_json())?;
+        }
+        Ok(())
+    }
+    /*
+        The author is the one who submits a post for review.
+    */
+    pub fn request_review(&mut self) -> Result<(), WorkflowError> {
+        let author = self.author.clone();
+        self.transition("request_review", &author, "request_review")?;
+        // Every review starts from scratch.
+        self.approvals.clear();
+        self.publish_at = None;
+        Ok(())
+    }
+    pub fn approve(&mut self, reviewer: &str) -> Result<(), ApprovalError> {
         /*
-            as_ref gives us a reference to value inside
-            the Option, instead of taking ownership.
-
-            At this point, when we call content on the &Box<dyn State>, 
-            deref coercion will take effect on the & and the Box so the 
-            content method will ultimately be called on the type that 
-            implements the State trait.
+            Post owns the author and the approvals collected so far, so it can refuse
+            the approval before asking the workflow for a transition.
         */
-        self.state.as_ref().unwrap().content(self)
-    }
-    pub fn request_review(&mut self) {
-        if let Some(s) = self.state.take() {
-            self.state = Some(s.request_review())
+        if reviewer == self.author {
+            return Err(ApprovalError::SelfApproval);
+        }
+        if self.state != PostState::PendingReview {
+            return Err(ApprovalError::NotPending(self.state.name()));
+        }
+        if self.approvals.iter().any(|approver| approver == reviewer) {
+            return Err(ApprovalError::DuplicateApproval(String::from(reviewer)));
+        }
+        self.approvals.push(String::from(reviewer));
+        if self.approvals.len() < self.required_approvals {
+            // Still in review, but who has signed off changed, so it goes in the log.
+            self.record("approve", self.state, self.state, reviewer);
+        } else if self.publish_at.is_some() {
+            let _ = self.transition("approve", reviewer, "approve_scheduled");
+        } else {
+            let _ = self.transition("approve", reviewer, "approve");
         }
+        Ok(())
     }
-    pub fn approve(&mut self) {
-        if let Some(s) = self.state.take() {
-            self.state = Some(s.approve())
+    pub fn reject(&mut self, reviewer: &str) -> Result<(), WorkflowError> {
+        self.transition("reject", reviewer, "reject")
+    }
+    /*
+        Picking a publication time doesn't change the state; it decides which state
+        the last approval leads to.
+    */
+    pub fn schedule(
+        &mut self,
+        reviewer: &str,
+        publish_at: SystemTime,
+    ) -> Result<(), WorkflowError> {
+        if self.state != PostState::PendingReview {
+            return Err(WorkflowError::InvalidTransition {
+                event: String::from("schedule"),
+                state: self.state.name(),
+            });
         }
+        self.publish_at = Some(publish_at);
+        self.record("schedule", self.state, self.state, reviewer);
+        Ok(())
     }
-}
-
-trait State {
-    fn request_review(self: Box<Self>) -> Box<dyn State>;
-    fn approve(self: Box<Self>) -> Box<dyn State>;
-    fn content<'a>(&self, post: &'a Post) -> &'a str {
-        "" // add default implementation, so we only need to selectively implement this method
+    pub fn archive(&mut self, actor: &str) -> Result<(), WorkflowError> {
+        self.transition("archive", actor, "archive")
     }
-}
-/*
-    Define different states using different structs;
-    each struct implements the State trait in a different
-    way. This lets different state objects emulate different
-    behaviour like we want.
-*/
-
-struct Draft {}
-
-impl State for Draft {
-    fn request_review(self: Box<Self>) -> Box<dyn State> {
-        Box::new(PendingReview {})
+    /*
+        Every workflow action asks PostState::fire for the next state, so the workflow
+        definition is the one place that decides what each state allows. The book
+        silently ignores the actions it refuses; here the caller gets the WorkflowError
+        back, and the action leaves no entry in the audit log.
+    */
+    fn transition(
+        &mut self,
+        action: &'static str,
+        actor: &str,
+        event: &str,
+    ) -> Result<(), WorkflowError> {
+        let from = self.state;
+        self.state = from.fire(event, &self.content)?;
+        self.record(action, from, self.state, actor);
+        Ok(())
     }
-    fn approve(self: Box<Self>) -> Box<dyn State> {
-        self
+    fn record(&mut self, action: &'static str, from: PostState, to: PostState, actor: &str) {
+        self.audit_log.push(Transition {
+            action,
+            from: from.name(),
+            to: to.name(),
+            actor: String::from(actor),
+            timestamp: SystemTime::now(),
+        });
     }
 }
 
-struct PendingReview {}
-
-impl State for PendingReview {
-    fn request_review(self: Box<Self>) -> Box<dyn State> {
-        self
-    }
-    fn approve(self: Box<Self>) -> Box<dyn State> {
-        Box::new(Published {})
+/*
+    Escapes the characters that JSON doesn't allow inside a string literal.
+*/
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
     }
+    escaped
 }
 
-struct Published {}
-
-impl State for Published {
-    fn request_review(self: Box<Self>) -> Box<dyn State> {
-        self
-    }
+/*
+    A line based diff built on the longest common subsequence of the two texts:
+    lines in the subsequence are unchanged, the rest were removed from old or added in new.
+    lcs[i][j] holds the length of the longest common subsequence of old[i..] and new[j..].
+*/
+fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
 
-    fn approve(self: Box<Self>) -> Box<dyn State> {
-        self
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
     }
 
-    fn content<'a>(&self, post: &'a Post) -> &'a str {
-        &post.content
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            result.push(DiffLine::Unchanged(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            result.push(DiffLine::Removed(old[i]));
+            i += 1;
+        } else {
+            result.push(DiffLine::Added(new[j]));
+            j += 1;
+        }
     }
+    result.extend(old[i..].iter().map(|line| DiffLine::Removed(line)));
+    result.extend(new[j..].iter().map(|line| DiffLine::Added(line)));
+    result
 }
 
 /*
@@ -107,45 +492,458 @@ impl State for Published {
     a Post does.
 */
 
-pub struct Post {
-    content: String,
-}
+/*
+    Instead of writing each state type and its transition methods by hand, we let
+    the workflow! invocation at the top of this file generate them from the same
+    definition as PostState: TypedPost<post_states::Draft> and so on, with one method
+    per transition. Changing a transition there changes both versions.
 
-pub struct DraftPost {
-    content: String,
-}
+    Type aliases keep the names from our hand-written version. The published post
+    is PublishedPost here, since Post is the runtime version above.
+*/
+pub type DraftPost = TypedPost<post_states::Draft>;
+pub type PendingReviewPost = TypedPost<post_states::PendingReview>;
+pub type PublishedPost = TypedPost<post_states::Published>;
 
-impl Post {
+impl DraftPost {
     pub fn new() -> DraftPost {
-        DraftPost {
-            content: String::new(),
-        }
+        DraftPost::start(String::new())
     }
 
-    pub fn content(&self) -> &str {
-        &self.content
+    pub fn add_text(&mut self, text: &str) {
+        self.data.push_str(text);
     }
 }
 
-impl DraftPost {
-    pub fn add_text(&mut self, text: &str) {
-        self.content.push_str(text);
-    }
-    pub fn request_review(self) -> PendingReviewPost {
-        PendingReviewPost {
-            content: self.content,
-        }
+impl Default for DraftPost {
+    fn default() -> DraftPost {
+        DraftPost::new()
     }
 }
 
-pub struct PendingReviewPost {
-    content: String,
+impl PublishedPost {
+    pub fn content(&self) -> &str {
+        &self.data
+    }
 }
 
-impl PendingReviewPost {
-    pub fn approve(self) -> Post {
-        Post {
-            content: self.content,
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn pending_post() -> Post {
+        let mut post = Post::new("alice");
+        post.add_text("I ate a salad for lunch today").unwrap();
+        post.request_review().unwrap();
+        post
+    }
+
+    fn refused(event: &str, state: &'static str) -> Result<(), WorkflowError> {
+        Err(WorkflowError::InvalidTransition {
+            event: String::from(event),
+            state,
+        })
+    }
+
+    fn approve_by_two(post: &mut Post) {
+        post.approve("bob").unwrap();
+        post.approve("carol").unwrap();
+    }
+
+    fn published_post() -> Post {
+        let mut post = pending_post();
+        approve_by_two(&mut post);
+        post
+    }
+
+    fn scheduled_post(publish_at: SystemTime) -> Post {
+        let mut post = pending_post();
+        post.schedule("bob", publish_at).unwrap();
+        approve_by_two(&mut post);
+        post
+    }
+
+    #[test]
+    fn draft_hides_content_and_ignores_other_transitions() {
+        let mut post = Post::new("alice");
+        post.add_text("I ate a salad for lunch today").unwrap();
+        assert_eq!(post.state_name(), "draft");
+        assert_eq!(post.content(), "");
+
+        assert_eq!(post.approve("bob"), Err(ApprovalError::NotPending("draft")));
+        assert_eq!(post.reject("bob"), refused("reject", "draft"));
+        assert_eq!(post.schedule("bob", SystemTime::now()), refused("schedule", "draft"));
+        assert_eq!(post.archive("alice"), refused("archive", "draft"));
+        assert_eq!(post.state_name(), "draft");
+        assert!(post.approvals().is_empty());
+    }
+
+    #[test]
+    fn draft_request_review_moves_to_pending_review() {
+        let post = pending_post();
+        assert_eq!(post.state_name(), "pending_review");
+        assert_eq!(post.content(), "");
+    }
+
+    #[test]
+    fn pending_review_ignores_request_review_and_archive() {
+        let mut post = pending_post();
+        assert_eq!(post.request_review(), refused("request_review", "pending_review"));
+        assert_eq!(post.archive("alice"), refused("archive", "pending_review"));
+        assert_eq!(post.state_name(), "pending_review");
+    }
+
+    #[test]
+    fn pending_review_needs_every_required_approval() {
+        let mut post = pending_post();
+        post.approve("bob").unwrap();
+        assert_eq!(post.state_name(), "pending_review");
+        assert_eq!(post.approvals(), ["bob"]);
+        assert_eq!(post.content(), "");
+
+        post.approve("carol").unwrap();
+        assert_eq!(post.state_name(), "published");
+        assert_eq!(post.content(), "I ate a salad for lunch today");
+    }
+
+    #[test]
+    fn pending_review_threshold_is_configurable() {
+        let mut post = Post::with_required_approvals("alice", 1);
+        post.request_review().unwrap();
+        post.approve("bob").unwrap();
+        assert_eq!(post.state_name(), "published");
+
+        let mut post = Post::with_required_approvals("alice", 3);
+        post.request_review().unwrap();
+        approve_by_two(&mut post);
+        assert_eq!(post.state_name(), "pending_review");
+        post.approve("dave").unwrap();
+        assert_eq!(post.state_name(), "published");
+    }
+
+    #[test]
+    fn author_cannot_approve_own_post() {
+        let mut post = pending_post();
+        assert_eq!(post.approve("alice"), Err(ApprovalError::SelfApproval));
+        assert!(post.approvals().is_empty());
+    }
+
+    #[test]
+    fn reviewer_cannot_approve_twice() {
+        let mut post = pending_post();
+        post.approve("bob").unwrap();
+        assert_eq!(
+            post.approve("bob"),
+            Err(ApprovalError::DuplicateApproval(String::from("bob")))
+        );
+        assert_eq!(post.state_name(), "pending_review");
+    }
+
+    #[test]
+    fn pending_review_reject_returns_to_draft_and_clears_approvals() {
+        let mut post = pending_post();
+        post.approve("bob").unwrap();
+        post.reject("bob").unwrap();
+        assert_eq!(post.state_name(), "draft");
+        assert_eq!(post.content(), "");
+
+        post.request_review().unwrap();
+        assert!(post.approvals().is_empty());
+    }
+
+    #[test]
+    fn pending_review_schedule_moves_to_scheduled_once_approved() {
+        let mut post = pending_post();
+        post.schedule("bob", SystemTime::now() + Duration::from_secs(3600)).unwrap();
+        assert_eq!(post.state_name(), "pending_review");
+
+        approve_by_two(&mut post);
+        assert_eq!(post.state_name(), "scheduled");
+    }
+
+    #[test]
+    fn scheduled_hides_content_until_publish_time() {
+        let post = scheduled_post(SystemTime::now() + Duration::from_secs(3600));
+        assert_eq!(post.content(), "");
+
+        let post = scheduled_post(SystemTime::now() - Duration::from_secs(60));
+        assert_eq!(post.content(), "I ate a salad for lunch today");
+    }
+
+    #[test]
+    fn scheduled_cannot_be_approved_again() {
+        let mut post = scheduled_post(SystemTime::now() + Duration::from_secs(3600));
+        assert_eq!(post.approve("dave"), Err(ApprovalError::NotPending("scheduled")));
+        assert_eq!(post.approve("bob"), Err(ApprovalError::NotPending("scheduled")));
+        assert_eq!(post.state_name(), "scheduled");
+        assert_eq!(post.content(), "");
+    }
+
+    #[test]
+    fn scheduled_reject_returns_to_draft() {
+        let mut post = scheduled_post(SystemTime::now() + Duration::from_secs(3600));
+        post.reject("bob").unwrap();
+        assert_eq!(post.state_name(), "draft");
+    }
+
+    #[test]
+    fn scheduled_archive_moves_to_archived() {
+        let publish_at = SystemTime::now() + Duration::from_secs(3600);
+        let mut post = scheduled_post(publish_at);
+        assert_eq!(post.request_review(), refused("request_review", "scheduled"));
+        assert_eq!(post.state_name(), "scheduled");
+        assert_eq!(post.schedule("bob", SystemTime::now()), refused("schedule", "scheduled"));
+        assert_eq!(post.publish_at(), Some(publish_at));
+
+        post.archive("alice").unwrap();
+        assert_eq!(post.state_name(), "archived");
+    }
+
+    #[test]
+    fn published_archive_hides_content() {
+        let mut post = published_post();
+        assert_eq!(post.request_review(), refused("request_review", "published"));
+        assert_eq!(post.approve("dave"), Err(ApprovalError::NotPending("published")));
+        assert_eq!(post.reject("bob"), refused("reject", "published"));
+        assert_eq!(post.schedule("bob", SystemTime::now()), refused("schedule", "published"));
+        assert_eq!(post.state_name(), "published");
+
+        post.archive("alice").unwrap();
+        assert_eq!(post.state_name(), "archived");
+        assert_eq!(post.content(), "");
+    }
+
+    #[test]
+    fn archived_ignores_every_transition() {
+        let mut post = published_post();
+        post.archive("alice").unwrap();
+
+        assert_eq!(post.request_review(), refused("request_review", "archived"));
+        assert_eq!(post.approve("dave"), Err(ApprovalError::NotPending("archived")));
+        assert_eq!(post.reject("bob"), refused("reject", "archived"));
+        assert_eq!(post.schedule("bob", SystemTime::now()), refused("schedule", "archived"));
+        assert_eq!(post.archive("alice"), refused("archive", "archived"));
+        assert_eq!(post.state_name(), "archived");
+        assert_eq!(post.content(), "");
+    }
+
+    #[test]
+    fn add_text_is_only_allowed_in_draft() {
+        let mut post = pending_post();
+        assert_eq!(
+            post.add_text(" and a sandwich"),
+            Err(EditError::NotDraft("pending_review"))
+        );
+
+        let mut post = published_post();
+        assert_eq!(
+            post.add_text(" and a sandwich"),
+            Err(EditError::NotDraft("published"))
+        );
+        assert_eq!(post.content(), "I ate a salad for lunch today");
+    }
+
+    #[test]
+    fn add_text_records_a_revision_per_change() {
+        let mut post = Post::new("alice");
+        post.add_text("Hello\n").unwrap();
+        post.add_text("World\n").unwrap();
+
+        let contents: Vec<&str> = post
+            .revisions()
+            .iter()
+            .map(|r| r.content.as_str())
+            .collect();
+        assert_eq!(contents, ["", "Hello\n", "Hello\nWorld\n"]);
+        assert_eq!(post.revisions()[2].number, 2);
+    }
+
+    #[test]
+    fn rejected_post_can_be_edited_again() {
+        let mut post = pending_post();
+        post.reject("bob").unwrap();
+        post.add_text(" with tomatoes").unwrap();
+        assert_eq!(post.revisions().len(), 3);
+    }
+
+    #[test]
+    fn diff_reports_added_and_removed_lines() {
+        let mut post = Post::new("alice");
+        post.add_text("one\ntwo\n").unwrap();
+        post.rollback(0).unwrap();
+        post.add_text("one\nthree\n").unwrap();
+
+        assert_eq!(
+            post.diff(1, 3).unwrap(),
+            [
+                DiffLine::Unchanged("one"),
+                DiffLine::Removed("two"),
+                DiffLine::Added("three"),
+            ]
+        );
+        assert_eq!(post.diff(1, 9), Err(EditError::NoSuchRevision(9)));
+    }
+
+    #[test]
+    fn rollback_restores_content_as_a_new_revision() {
+        let mut post = Post::new("alice");
+        post.add_text("first draft").unwrap();
+        post.add_text(", second thoughts").unwrap();
+
+        post.rollback(1).unwrap();
+        assert_eq!(post.revisions().len(), 4);
+        assert_eq!(post.revisions()[3].content, "first draft");
+
+        post.request_review().unwrap();
+        approve_by_two(&mut post);
+        assert_eq!(post.content(), "first draft");
+    }
+
+    #[test]
+    fn rollback_is_only_allowed_in_draft() {
+        let mut post = pending_post();
+        assert_eq!(post.rollback(0), Err(EditError::NotDraft("pending_review")));
+
+        let mut post = Post::new("alice");
+        assert_eq!(post.rollback(5), Err(EditError::NoSuchRevision(5)));
+    }
+
+    #[test]
+    fn audit_log_records_each_accepted_transition() {
+        let mut post = published_post();
+        post.archive("alice").unwrap();
+
+        let log: Vec<(&str, &str, &str, &str)> = post
+            .audit_log()
+            .map(|t| (t.action, t.from, t.to, t.actor.as_str()))
+            .collect();
+        assert_eq!(
+            log,
+            [
+                ("request_review", "draft", "pending_review", "alice"),
+                ("approve", "pending_review", "pending_review", "bob"),
+                ("approve", "pending_review", "published", "carol"),
+                ("archive", "published", "archived", "alice"),
+            ]
+        );
+    }
+
+    #[test]
+    fn audit_log_skips_ignored_and_refused_actions() {
+        let mut post = Post::new("alice");
+        post.approve("bob").unwrap_err();
+        assert_eq!(post.archive("alice"), refused("archive", "draft"));
+        assert_eq!(post.audit_log().count(), 0);
+
+        post.request_review().unwrap();
+        post.approve("alice").unwrap_err();
+        assert_eq!(post.request_review(), refused("request_review", "pending_review"));
+        assert_eq!(post.audit_log().count(), 1);
+    }
+
+    #[test]
+    fn audit_log_timestamps_are_in_order() {
+        let before = SystemTime::now();
+        let post = published_post();
+        let after = SystemTime::now();
+
+        let timestamps: Vec<SystemTime> = post.audit_log().map(|t| t.timestamp).collect();
+        assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(timestamps[0] >= before && timestamps[2] <= after);
+    }
+
+    #[test]
+    fn audit_log_is_written_as_json_lines() {
+        let mut post = Post::new("alice \"al\" smith");
+        post.request_review().unwrap();
+
+        let mut out = Vec::new();
+        post.write_audit_log(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let millis = post
+            .audit_log()
+            .next()
+            .unwrap()
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        assert_eq!(
+            out,
+            format!(
+                "{{\"action\":\"request_review\",\"from\":\"draft\",\"to\":\"pending_review\",\"actor\":\"alice \\\"al\\\" smith\",\"timestamp_ms\":{millis}}}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn audit_log_keeps_times_before_the_epoch() {
+        let transition = Transition {
+            action: "archive",
+            from: "published",
+            to: "archived",
+            actor: String::from("alice"),
+            timestamp: UNIX_EPOCH - Duration::from_millis(86_400_123),
+        };
+        assert!(transition.to_json().ends_with("\"timestamp_ms\":-86400123}"));
+    }
+
+    #[test]
+    fn render_html_is_only_available_once_published() {
+        let mut post = Post::with_required_approvals("alice", 1);
+        post.add_text("# Lunch\n\nI ate a *salad* <b>today</b>").unwrap();
+        assert_eq!(post.render_html(), None);
+
+        post.request_review().unwrap();
+        assert_eq!(post.render_html(), None);
+
+        post.approve("bob").unwrap();
+        assert_eq!(
+            post.render_html().unwrap(),
+            "<h1>Lunch</h1>\n<p>I ate a <em>salad</em> &lt;b&gt;today&lt;/b&gt;</p>\n"
+        );
+
+        post.archive("alice").unwrap();
+        assert_eq!(post.render_html(), None);
+    }
+
+    #[test]
+    fn post_state_comes_from_the_workflow() {
+        let mut post = scheduled_post(SystemTime::now() + Duration::from_secs(3600));
+        assert_eq!(post.state(), PostState::Scheduled);
+        assert_eq!(PostState::from_name(post.state_name()), Some(post.state()));
+
+        post.archive("alice").unwrap();
+        assert_eq!(post.state(), PostState::Archived);
+        assert!(PostState::TRANSITIONS
+            .iter()
+            .all(|(_, from, _)| *from != PostState::Archived));
+    }
+
+    #[test]
+    fn type_states_share_the_workflow() {
+        let mut post = DraftPost::new();
+        post.add_text("I ate a salad for lunch today");
+        let post = post.request_review().reject().request_review();
+        assert_eq!(PendingReviewPost::STATE, PostState::PendingReview);
+
+        let post = post.approve();
+        assert_eq!(post.content(), "I ate a salad for lunch today");
+        assert_eq!(PublishedPost::STATE, PostState::Published);
+        assert_eq!(post.archive().into_data(), "I ate a salad for lunch today");
+    }
+
+    #[test]
+    fn render_html_follows_the_schedule() {
+        let post = scheduled_post(SystemTime::now() + Duration::from_secs(3600));
+        assert_eq!(post.render_html(), None);
+
+        let post = scheduled_post(SystemTime::now() - Duration::from_secs(60));
+        assert_eq!(
+            post.render_html().unwrap(),
+            "<p>I ate a salad for lunch today</p>\n"
+        );
     }
 }
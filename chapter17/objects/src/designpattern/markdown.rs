@@ -0,0 +1,415 @@
+/*
+    Renders the Markdown subset our posts use into HTML:
+
+        # Heading (levels 1 to 6)
+        *emphasis* or _emphasis_, **strong** or __strong__, `inline code`
+        [link text](https://example.com)
+        - unordered list items (also * and +)
+        1. ordered list items
+        ``` fenced code blocks ```
+
+    Post content is written by users, so the output must be safe to embed in a page.
+    Raw HTML in the content is never passed through: every character of text is escaped,
+    and links only keep URLs with a safe scheme, so javascript: links can't sneak in.
+*/
+
+pub fn render(markdown: &str) -> String {
+    let mut renderer = Renderer {
+        html: String::new(),
+        paragraph: Vec::new(),
+        list: None,
+    };
+    let mut code_block = false;
+
+    for line in markdown.lines() {
+        if let Some(info) = line.trim_start().strip_prefix("```") {
+            if code_block {
+                renderer.html.push_str("</code></pre>\n");
+            } else {
+                renderer.close_blocks();
+                renderer.open_code_block(info.trim());
+            }
+            code_block = !code_block;
+        } else if code_block {
+            renderer.html.push_str(&escape_html(line));
+            renderer.html.push('\n');
+        } else if line.trim().is_empty() {
+            renderer.close_blocks();
+        } else if let Some((level, text)) = heading(line) {
+            renderer.close_blocks();
+            renderer
+                .html
+                .push_str(&format!("<h{level}>{}</h{level}>\n", render_inline(text)));
+        } else if let Some((kind, text)) = list_item(line) {
+            renderer.close_paragraph();
+            renderer.open_list(kind);
+            renderer
+                .html
+                .push_str(&format!("<li>{}</li>\n", render_inline(text)));
+        } else {
+            renderer.close_list();
+            renderer.paragraph.push(line.trim());
+        }
+    }
+
+    if code_block {
+        renderer.html.push_str("</code></pre>\n"); // close a block the author forgot to end
+    }
+    renderer.close_blocks();
+    renderer.html
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ListKind {
+    Unordered,
+    Ordered,
+}
+
+impl ListKind {
+    fn tag(&self) -> &'static str {
+        match self {
+            ListKind::Unordered => "ul",
+            ListKind::Ordered => "ol",
+        }
+    }
+}
+
+/*
+    Keeps track of the blocks that are still open while we walk through the lines.
+*/
+struct Renderer<'a> {
+    html: String,
+    paragraph: Vec<&'a str>,
+    list: Option<ListKind>,
+}
+
+impl<'a> Renderer<'a> {
+    fn open_code_block(&mut self, language: &str) {
+        let language: String = language
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+            .collect();
+        if language.is_empty() {
+            self.html.push_str("<pre><code>");
+        } else {
+            self.html
+                .push_str(&format!("<pre><code class=\"language-{language}\">"));
+        }
+    }
+
+    fn open_list(&mut self, kind: ListKind) {
+        if self.list != Some(kind) {
+            self.close_list();
+            self.html.push_str(&format!("<{}>\n", kind.tag()));
+            self.list = Some(kind);
+        }
+    }
+
+    fn close_list(&mut self) {
+        if let Some(kind) = self.list.take() {
+            self.html.push_str(&format!("</{}>\n", kind.tag()));
+        }
+    }
+
+    fn close_paragraph(&mut self) {
+        if !self.paragraph.is_empty() {
+            let text = self.paragraph.join(" ");
+            self.html
+                .push_str(&format!("<p>{}</p>\n", render_inline(&text)));
+            self.paragraph.clear();
+        }
+    }
+
+    fn close_blocks(&mut self) {
+        self.close_paragraph();
+        self.close_list();
+    }
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    line[level..]
+        .strip_prefix(' ')
+        .map(|text| (level, text.trim()))
+}
+
+fn list_item(line: &str) -> Option<(ListKind, &str)> {
+    let line = line.trim_start();
+    for marker in ["- ", "* ", "+ "] {
+        if let Some(text) = line.strip_prefix(marker) {
+            return Some((ListKind::Unordered, text.trim()));
+        }
+    }
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 {
+        if let Some(text) = line[digits..].strip_prefix(". ") {
+            return Some((ListKind::Ordered, text.trim()));
+        }
+    }
+    None
+}
+
+/*
+    Renders the inline markup inside a block. Emphasis is only turned into a tag when
+    its closing delimiter exists, so the tags we emit are always balanced.
+*/
+fn render_inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut html = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == '`' {
+            if let Some(end) = find(&chars, i + 1, &['`']) {
+                let code: String = chars[i + 1..end].iter().collect();
+                html.push_str(&format!("<code>{}</code>", escape_html(&code)));
+                i = end + 1;
+                continue;
+            }
+        }
+
+        if c == '[' {
+            if let Some((label, url, end)) = link(&chars, i) {
+                let label = render_inline(&label);
+                match safe_url(&url) {
+                    Some(url) => {
+                        html.push_str(&format!("<a href=\"{}\">{label}</a>", escape_html(url)))
+                    }
+                    None => html.push_str(&label),
+                }
+                i = end;
+                continue;
+            }
+        }
+
+        if c == '*' || c == '_' {
+            let after_word = i > 0 && chars[i - 1].is_alphanumeric();
+            if !(c == '_' && after_word) {
+                let strong = chars.get(i + 1) == Some(&c);
+                let (delimiter, tag): (&[char], &str) = if strong {
+                    (&[c, c], "strong")
+                } else {
+                    (&[c], "em")
+                };
+                let start = i + delimiter.len();
+                if let Some(end) = find(&chars, start, delimiter) {
+                    if end > start {
+                        let inner: String = chars[start..end].iter().collect();
+                        html.push_str(&format!("<{tag}>{}</{tag}>", render_inline(&inner)));
+                        i = end + delimiter.len();
+                        continue;
+                    }
+                }
+            }
+        }
+
+        html.push_str(&escape_html(&c.to_string()));
+        i += 1;
+    }
+
+    html
+}
+
+/*
+    Returns the index where the delimiter next appears, starting the search at from.
+*/
+fn find(chars: &[char], from: usize, delimiter: &[char]) -> Option<usize> {
+    (from..chars.len()).find(|&i| chars[i..].starts_with(delimiter))
+}
+
+/*
+    Parses [label](url) starting at the opening bracket, returning the label, the url
+    and the index just past the closing parenthesis. Parentheses inside the url must
+    be balanced, as in https://en.wikipedia.org/wiki/Rust_(language), so the url ends
+    at the first ) that closes no ( of its own.
+*/
+fn link(chars: &[char], start: usize) -> Option<(String, String, usize)> {
+    let label_end = find(chars, start + 1, &[']', '('])?;
+    let mut depth = 0;
+    let url_end = (label_end + 2..chars.len()).find(|&i| match chars[i] {
+        '(' => {
+            depth += 1;
+            false
+        }
+        ')' if depth == 0 => true,
+        ')' => {
+            depth -= 1;
+            false
+        }
+        _ => false,
+    })?;
+    let label = chars[start + 1..label_end].iter().collect();
+    let url = chars[label_end + 2..url_end].iter().collect();
+    Some((label, url, url_end + 1))
+}
+
+/*
+    Only http, https and mailto links, or relative links without a scheme, are kept.
+    A url starting with // only leaves out the scheme: browsers take it to another
+    host, so it isn't relative. They read a backslash there as a slash, too, and they
+    skip tabs and newlines anywhere in a url, so /<tab>/ is // as well: a url with
+    whitespace or control characters inside is dropped before any of these checks.
+*/
+fn safe_url(url: &str) -> Option<&str> {
+    let url = url.trim();
+    if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return None;
+    }
+    let lowercase = url.to_ascii_lowercase();
+    let allowed = ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| lowercase.starts_with(scheme));
+    let other_host = url.replace('\\', "/").starts_with("//");
+    let relative =
+        !other_host && (!url.contains(':') || url.starts_with('/') || url.starts_with('#'));
+
+    if url.is_empty() || !(allowed || relative) {
+        None
+    } else {
+        Some(url)
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_headings_and_paragraphs() {
+        assert_eq!(
+            render("# Lunch\n\nI ate a salad\nfor lunch today.\n###### Small"),
+            "<h1>Lunch</h1>\n<p>I ate a salad for lunch today.</p>\n<h6>Small</h6>\n"
+        );
+        assert_eq!(render("#hashtag"), "<p>#hashtag</p>\n");
+    }
+
+    #[test]
+    fn renders_emphasis_and_inline_code() {
+        assert_eq!(
+            render("*so* **very** _fresh_ __greens__ and `x < y`"),
+            "<p><em>so</em> <strong>very</strong> <em>fresh</em> <strong>greens</strong> and <code>x &lt; y</code></p>\n"
+        );
+    }
+
+    #[test]
+    fn leaves_unbalanced_and_intraword_markers_as_text() {
+        assert_eq!(render("2 * 3 = 6"), "<p>2 * 3 = 6</p>\n");
+        assert_eq!(render("snake_case_name"), "<p>snake_case_name</p>\n");
+    }
+
+    #[test]
+    fn renders_lists() {
+        assert_eq!(
+            render("- lettuce\n* **tomato**\n1. wash\n2. chop\n\nDone"),
+            "<ul>\n<li>lettuce</li>\n<li><strong>tomato</strong></li>\n</ul>\n\
+             <ol>\n<li>wash</li>\n<li>chop</li>\n</ol>\n<p>Done</p>\n"
+        );
+    }
+
+    #[test]
+    fn renders_code_blocks_without_inline_markup() {
+        assert_eq!(
+            render("```rust\nlet x = *y;\n# not a heading\n```"),
+            "<pre><code class=\"language-rust\">let x = *y;\n# not a heading\n</code></pre>\n"
+        );
+        assert_eq!(
+            render("```\n<b>unterminated"),
+            "<pre><code>&lt;b&gt;unterminated\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn renders_links() {
+        assert_eq!(
+            render("See [the **book**](https://doc.rust-lang.org/book/) or [home](/)"),
+            "<p>See <a href=\"https://doc.rust-lang.org/book/\">the <strong>book</strong></a> or <a href=\"/\">home</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn escapes_raw_html() {
+        assert_eq!(
+            render("<script>alert('hi')</script> & more"),
+            "<p>&lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt; &amp; more</p>\n"
+        );
+        assert_eq!(
+            render("# <img src=x onerror=alert(1)>"),
+            "<h1>&lt;img src=x onerror=alert(1)&gt;</h1>\n"
+        );
+        assert_eq!(
+            render("- <em>not</em> markdown"),
+            "<ul>\n<li>&lt;em&gt;not&lt;/em&gt; markdown</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn drops_unsafe_link_targets() {
+        assert_eq!(render("[click](javascript:alert(1))"), "<p>click</p>\n");
+        assert_eq!(render("[click](JavaScript:alert)"), "<p>click</p>\n");
+        assert_eq!(render("[data](data:text/html,hi)"), "<p>data</p>\n");
+    }
+
+    #[test]
+    fn escapes_quotes_in_link_targets() {
+        assert_eq!(
+            render("[x](https://example.com/\"onmouseover=\"alert(1))"),
+            "<p><a href=\"https://example.com/&quot;onmouseover=&quot;alert(1)\">x</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn link_targets_may_contain_balanced_parentheses() {
+        assert_eq!(
+            render("[Rust](https://en.wikipedia.org/wiki/Rust_(language)) (the game)"),
+            "<p><a href=\"https://en.wikipedia.org/wiki/Rust_(language)\">Rust</a> (the game)</p>\n"
+        );
+        assert_eq!(
+            render("[open](https://example.com/(x)"),
+            "<p>[open](https://example.com/(x)</p>\n"
+        );
+    }
+
+    #[test]
+    fn drops_protocol_relative_link_targets() {
+        assert_eq!(render("[evil](//evil.example/login)"), "<p>evil</p>\n");
+        assert_eq!(render("[evil](/\\evil.example)"), "<p>evil</p>\n");
+        assert_eq!(render("[evil](/\t/evil.example)"), "<p>evil</p>\n");
+        assert_eq!(render("[evil](/\n/evil.example)"), "<p>evil</p>\n");
+        assert_eq!(render("[evil](/\u{0}/evil.example)"), "<p>evil</p>\n");
+        assert_eq!(safe_url("/\t/evil.example"), None);
+        assert_eq!(safe_url("/\n/evil.example"), None);
+        assert_eq!(safe_url("/\r\n/evil.example"), None);
+        assert_eq!(
+            render("[home](/posts/1)"),
+            "<p><a href=\"/posts/1\">home</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn strips_unsafe_characters_from_code_block_language() {
+        assert_eq!(
+            render("```\"><script>\ncode\n```"),
+            "<pre><code class=\"language-script\">code\n</code></pre>\n"
+        );
+    }
+}
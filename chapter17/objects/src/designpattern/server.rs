@@ -0,0 +1,688 @@
+/*
+    A small HTTP/1.1 server that exposes the Post workflow as a REST API, built only on
+    the standard library:
+
+        POST /posts                        body: author        -> 201 {"id":1}
+        POST /posts/{id}/text              body: text to add   -> 204
+        POST /posts/{id}/request_review                        -> 204
+        POST /posts/{id}/approve           body: reviewer      -> 204
+        GET  /posts/{id}                                       -> 200 {"id":1,"state":"draft","content":""}
+
+    Actions that the post's current state doesn't allow are answered with 409 Conflict,
+    and those a workflow guard refuses with 422 Unprocessable Entity.
+    Reading a post that isn't published returns empty content, since Post::content
+    already hides it.
+
+    The concurrency follows chapter 16: the posts live in an Arc<Mutex<_>> shared by
+    every worker thread, and connections are handed to a fixed set of workers through
+    an mpsc channel whose receiver is itself shared behind an Arc<Mutex<_>>.
+
+    With only a few workers, a client that connects and then sends nothing, or sends
+    an endless header, could hold a worker forever, and with it the shutdown, which
+    waits for the workers. So the whole request must arrive within a timeout (408
+    Request Timeout), and lines and headers are capped (431 Request Header Fields Too
+    Large).
+*/
+
+use super::{escape_json, ApprovalError, Post, WorkflowError};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const WORKERS: usize = 4;
+const MAX_BODY_BYTES: usize = 64 * 1024;
+const MAX_LINE_BYTES: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Blog {
+    posts: HashMap<u64, Post>,
+    next_id: u64,
+}
+
+pub struct BlogServer {
+    addr: SocketAddr,
+    stopping: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl BlogServer {
+    /*
+        Binds to addr and starts serving on a background thread. Bind to port 0
+        (for example "127.0.0.1:0") to let the OS pick a free port, then ask
+        local_addr which one it chose.
+    */
+    pub fn start<A: ToSocketAddrs>(addr: A) -> io::Result<BlogServer> {
+        BlogServer::with_read_timeout(addr, READ_TIMEOUT)
+    }
+
+    /*
+        Like start, but gives each client at most read_timeout to send its request.
+    */
+    pub fn with_read_timeout<A: ToSocketAddrs>(
+        addr: A,
+        read_timeout: Duration,
+    ) -> io::Result<BlogServer> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let blog = Arc::new(Mutex::new(Blog {
+            posts: HashMap::new(),
+            next_id: 1,
+        }));
+
+        let acceptor_stopping = Arc::clone(&stopping);
+        let acceptor = thread::spawn(move || {
+            let pool = ThreadPool::new(WORKERS);
+            for stream in listener.incoming() {
+                if acceptor_stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let blog = Arc::clone(&blog);
+                    pool.execute(move || handle_connection(stream, &blog, read_timeout));
+                }
+            }
+            // dropping the pool waits for the workers to finish their connections,
+            // which the read timeout keeps short
+        });
+
+        Ok(BlogServer {
+            addr,
+            stopping,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn shutdown(self) {
+        // the Drop implementation does the work
+    }
+}
+
+impl Drop for BlogServer {
+    /*
+        The acceptor thread is blocked waiting for a connection, so after setting the
+        flag we connect once ourselves to wake it up and let it notice.
+    */
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect(self.addr);
+        if let Some(acceptor) = self.acceptor.take() {
+            acceptor.join().unwrap();
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/*
+    Each worker loops, taking the next job from the shared receiver. The lock is
+    released as soon as recv returns, because the MutexGuard is a temporary dropped
+    at the end of the let statement, so other workers can pick up jobs while this
+    one is busy.
+*/
+struct ThreadPool {
+    workers: Vec<JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+impl ThreadPool {
+    fn new(size: usize) -> ThreadPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break, // the sender was dropped, so we're shutting down
+                    }
+                })
+            })
+            .collect();
+
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender.as_ref().unwrap().send(Box::new(job)).unwrap();
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take()); // closes the channel
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: String,
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: String) -> Response {
+        Response { status, body }
+    }
+
+    fn no_content() -> Response {
+        Response {
+            status: 204,
+            body: String::new(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response {
+            status,
+            body: format!("{{\"error\":\"{}\"}}", escape_json(message)),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            201 => "Created",
+            204 => "No Content",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            422 => "Unprocessable Entity",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(
+            out,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.reason(),
+            self.body.len(),
+            self.body
+        )?;
+        out.flush()
+    }
+}
+
+/*
+    The deadline starts when a worker takes the connection rather than when it was
+    accepted: waiting in the queue for a free worker isn't the client's fault.
+*/
+fn handle_connection(mut stream: TcpStream, blog: &Mutex<Blog>, read_timeout: Duration) {
+    let deadline = Instant::now() + read_timeout;
+    match read_request(DeadlineReader::new(&stream, deadline)) {
+        Ok(request) => {
+            let _ = route(&request, blog).write_to(&mut stream);
+        }
+        Err(response) => {
+            let _ = response.write_to(&mut stream);
+            /*
+                The client may still be sending the rest of the request we refused.
+                Closing with unread data makes the OS reset the connection, which can
+                throw away our response before the client reads it, so we stop writing
+                and read what is left first, bounded by one more read timeout and size.
+            */
+            let _ = stream.shutdown(Shutdown::Write);
+            let rest = DeadlineReader::new(&stream, Instant::now() + read_timeout);
+            let _ = io::copy(&mut rest.take(MAX_BODY_BYTES as u64), &mut io::sink());
+        }
+    }
+}
+
+/*
+    A timeout on the socket only limits each read, so a client sending one byte at a
+    time could keep a request going forever. Before every read we shorten the timeout
+    to what is left until the deadline, and fail with TimedOut once it has passed.
+*/
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl<'a> DeadlineReader<'a> {
+    fn new(stream: &'a TcpStream, deadline: Instant) -> DeadlineReader<'a> {
+        DeadlineReader { stream, deadline }
+    }
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+/*
+    Reads the request line, the headers and, when there is a Content-Length header,
+    the body. Each connection carries a single request.
+*/
+fn read_request(stream: DeadlineReader) -> Result<Request, Response> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    read_line(&mut reader, &mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") => {
+            (String::from(method), String::from(path))
+        }
+        _ => return Err(Response::error(400, "malformed request line")),
+    };
+
+    let mut content_length = 0;
+    let mut headers = 0;
+    loop {
+        let mut header = String::new();
+        read_line(&mut reader, &mut header)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(Response::error(431, "too many headers"));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| Response::error(400, "invalid Content-Length"))?;
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_BYTES {
+        return Err(Response::error(413, "request body is too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).map_err(read_error)?;
+    let body = String::from_utf8(body).map_err(|_| Response::error(400, "body is not UTF-8"))?;
+
+    Ok(Request { method, path, body })
+}
+
+/*
+    Reads one line, but no more than MAX_LINE_BYTES of it, so a client can't make us
+    buffer an endless line.
+*/
+fn read_line(reader: &mut BufReader<DeadlineReader>, line: &mut String) -> Result<(), Response> {
+    let limit = MAX_LINE_BYTES as u64;
+    let read = reader
+        .by_ref()
+        .take(limit)
+        .read_line(line)
+        .map_err(read_error)?;
+    if read as u64 == limit && !line.ends_with('\n') {
+        return Err(Response::error(431, "request line or header is too long"));
+    }
+    Ok(())
+}
+
+/*
+    A read that times out fails with WouldBlock on some platforms and TimedOut on others.
+*/
+fn read_error(error: io::Error) -> Response {
+    match error.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            Response::error(408, "timed out waiting for the request")
+        }
+        _ => Response::error(400, "malformed request"),
+    }
+}
+
+fn route(request: &Request, blog: &Mutex<Blog>) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["posts"]) => create(blog, request.body.trim()),
+        ("GET", ["posts", id]) => with_post(blog, id, |id, post| {
+            Response::json(
+                200,
+                format!(
+                    "{{\"id\":{id},\"state\":\"{}\",\"content\":\"{}\"}}",
+                    post.state_name(),
+                    escape_json(post.content())
+                ),
+            )
+        }),
+        ("POST", ["posts", id, "text"]) => {
+            with_post(blog, id, |_, post| match post.add_text(&request.body) {
+                Ok(()) => Response::no_content(),
+                Err(e) => Response::error(409, &e.to_string()),
+            })
+        }
+        ("POST", ["posts", id, "request_review"]) => {
+            with_post(blog, id, |_, post| match post.request_review() {
+                Ok(()) => Response::no_content(),
+                Err(e) => workflow_error(&e),
+            })
+        }
+        ("POST", ["posts", id, "approve"]) => with_post(blog, id, |_, post| {
+            let reviewer = request.body.trim();
+            if reviewer.is_empty() {
+                return Response::error(400, "the body must name the reviewer");
+            }
+            match post.approve(reviewer) {
+                Ok(()) => Response::no_content(),
+                Err(ApprovalError::SelfApproval) => {
+                    Response::error(403, &ApprovalError::SelfApproval.to_string())
+                }
+                // NotPending and DuplicateApproval: the post's state doesn't allow it.
+                Err(e) => Response::error(409, &e.to_string()),
+            }
+        }),
+        _ => Response::error(404, "no such route"),
+    }
+}
+
+fn create(blog: &Mutex<Blog>, author: &str) -> Response {
+    if author.is_empty() {
+        return Response::error(400, "the body must name the author");
+    }
+    let mut blog = blog.lock().unwrap();
+    let id = blog.next_id;
+    blog.next_id += 1;
+    blog.posts.insert(id, Post::new(author));
+    Response::json(201, format!("{{\"id\":{id}}}"))
+}
+
+/*
+    Looks the post up and runs f on it while holding the lock, so requests for the
+    same post can't interleave half way through an action.
+*/
+fn with_post<F>(blog: &Mutex<Blog>, id: &str, f: F) -> Response
+where
+    F: FnOnce(u64, &mut Post) -> Response,
+{
+    let id: u64 = match id.parse() {
+        Ok(id) => id,
+        Err(_) => return Response::error(404, "no such post"),
+    };
+    let mut blog = blog.lock().unwrap();
+    match blog.posts.get_mut(&id) {
+        Some(post) => f(id, post),
+        None => Response::error(404, "no such post"),
+    }
+}
+
+/*
+    The state not allowing the action is a conflict with the post as it is now; a
+    guard refusing it means the post's content isn't fit for it yet.
+*/
+fn workflow_error(error: &WorkflowError) -> Response {
+    match error {
+        WorkflowError::InvalidTransition { .. } => Response::error(409, &error.to_string()),
+        WorkflowError::GuardFailed { .. } => Response::error(422, &error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(server: &BlogServer, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    fn start() -> BlogServer {
+        BlogServer::start("127.0.0.1:0").unwrap()
+    }
+
+    fn send_raw(server: &BlogServer, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        // The server may answer and close before reading everything we send.
+        let _ = stream.write_all(request);
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn idle_clients_do_not_block_shutdown() {
+        let server =
+            BlogServer::with_read_timeout("127.0.0.1:0", Duration::from_millis(200)).unwrap();
+        let idle: Vec<TcpStream> = (0..WORKERS + 1)
+            .map(|_| TcpStream::connect(server.local_addr()).unwrap())
+            .collect();
+
+        // Every worker is stuck on an idle client until the timeout frees it.
+        let started = Instant::now();
+        assert_eq!(send(&server, "POST", "/posts", "alice").0, 201);
+        server.shutdown();
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        for mut stream in idle {
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+        }
+    }
+
+    #[test]
+    fn trickling_clients_time_out() {
+        let server =
+            BlogServer::with_read_timeout("127.0.0.1:0", Duration::from_millis(300)).unwrap();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream
+            .write_all(b"GET /posts/1 HTTP/1.1\r\nX-Slow: ")
+            .unwrap();
+
+        // One byte every 50ms never lets a single read wait 300ms.
+        let mut trickle = stream.try_clone().unwrap();
+        let trickler = thread::spawn(move || {
+            for _ in 0..60 {
+                if trickle.write_all(b"x").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let started = Instant::now();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        trickler.join().unwrap();
+    }
+
+    #[test]
+    fn long_lines_and_too_many_headers_are_refused() {
+        let server = start();
+
+        let long_path = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_BYTES));
+        assert!(send_raw(&server, long_path.as_bytes()).starts_with("HTTP/1.1 431"));
+
+        let mut many_headers = String::from("GET /posts/1 HTTP/1.1\r\n");
+        for i in 0..=MAX_HEADERS {
+            many_headers.push_str(&format!("X-Header-{i}: x\r\n"));
+        }
+        many_headers.push_str("\r\n");
+        assert!(send_raw(&server, many_headers.as_bytes()).starts_with("HTTP/1.1 431"));
+
+        let just_enough = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(100));
+        assert!(send_raw(&server, just_enough.as_bytes()).starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn full_workflow_over_http() {
+        let server = start();
+        assert_eq!(
+            send(&server, "POST", "/posts", "alice"),
+            (201, String::from("{\"id\":1}"))
+        );
+        assert_eq!(
+            send(&server, "POST", "/posts/1/text", "I ate a \"salad\"").0,
+            204
+        );
+        assert_eq!(
+            send(&server, "GET", "/posts/1", ""),
+            (
+                200,
+                String::from("{\"id\":1,\"state\":\"draft\",\"content\":\"\"}")
+            )
+        );
+
+        assert_eq!(send(&server, "POST", "/posts/1/request_review", "").0, 204);
+        assert_eq!(send(&server, "POST", "/posts/1/approve", "bob").0, 204);
+        assert_eq!(
+            send(&server, "GET", "/posts/1", ""),
+            (
+                200,
+                String::from("{\"id\":1,\"state\":\"pending_review\",\"content\":\"\"}")
+            )
+        );
+
+        assert_eq!(send(&server, "POST", "/posts/1/approve", "carol").0, 204);
+        assert_eq!(
+            send(&server, "GET", "/posts/1", ""),
+            (
+                200,
+                String::from(
+                    "{\"id\":1,\"state\":\"published\",\"content\":\"I ate a \\\"salad\\\"\"}"
+                )
+            )
+        );
+        server.shutdown();
+    }
+
+    #[test]
+    fn invalid_transitions_return_conflict() {
+        let server = start();
+        send(&server, "POST", "/posts", "alice");
+
+        assert_eq!(send(&server, "POST", "/posts/1/approve", "bob").0, 409);
+        assert_eq!(send(&server, "POST", "/posts/1/request_review", "").0, 204);
+        assert_eq!(
+            send(&server, "POST", "/posts/1/request_review", ""),
+            (
+                409,
+                String::from(
+                    "{\"error\":\"request_review is not allowed in the pending_review state\"}"
+                )
+            )
+        );
+        assert_eq!(send(&server, "POST", "/posts/1/text", "more").0, 409);
+
+        assert_eq!(send(&server, "POST", "/posts/1/approve", "bob").0, 204);
+        assert_eq!(send(&server, "POST", "/posts/1/approve", "bob").0, 409);
+
+        assert_eq!(send(&server, "POST", "/posts/1/approve", "carol").0, 204);
+        assert_eq!(
+            send(&server, "POST", "/posts/1/approve", "dave"),
+            (
+                409,
+                String::from(
+                    "{\"error\":\"posts can only be approved while pending review, not while published\"}"
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn guard_failures_are_unprocessable() {
+        let error = WorkflowError::GuardFailed {
+            event: "request_review",
+            state: "draft",
+        };
+        assert_eq!(workflow_error(&error).status, 422);
+    }
+
+    #[test]
+    fn approvals_are_validated() {
+        let server = start();
+        send(&server, "POST", "/posts", "alice");
+        send(&server, "POST", "/posts/1/request_review", "");
+
+        assert_eq!(send(&server, "POST", "/posts/1/approve", "alice").0, 403);
+        assert_eq!(send(&server, "POST", "/posts/1/approve", "").0, 400);
+    }
+
+    #[test]
+    fn unknown_posts_and_routes_are_not_found() {
+        let server = start();
+        assert_eq!(send(&server, "GET", "/posts/7", "").0, 404);
+        assert_eq!(send(&server, "GET", "/posts/abc", "").0, 404);
+        assert_eq!(send(&server, "DELETE", "/posts", "").0, 404);
+        assert_eq!(send(&server, "POST", "/posts", "").0, 400);
+    }
+
+    #[test]
+    fn malformed_requests_are_rejected() {
+        let server = start();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.write_all(b"nonsense\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+    }
+
+    #[test]
+    fn concurrent_clients_get_distinct_posts() {
+        let server = Arc::new(start());
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let server = Arc::clone(&server);
+                thread::spawn(move || {
+                    let author = format!("author{i}");
+                    let (status, body) = send(&server, "POST", "/posts", &author);
+                    assert_eq!(status, 201);
+                    body
+                })
+            })
+            .collect();
+
+        let mut ids: Vec<String> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 8);
+    }
+}
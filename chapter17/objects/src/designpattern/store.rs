@@ -0,0 +1,513 @@
+/*
+    A file based store for posts, so a blog service can restart without losing drafts.
+
+    Each post lives in its own <id>.post file. The file is plain text with one field
+    per line, written as a key followed by a space and the value. Values that may contain
+    newlines (content, actors) are escaped so every field stays on a single line:
+
+        post v1
+        author alice
+        required_approvals 2
+        state pending_review
+        approval bob
+        publish_at 1700000000000
+        revision I ate a salad\nfor lunch
+        transition request_review draft pending_review 1700000000000 alice
+
+    The state is saved by the name the workflow gives it, together with the approvals
+    and the publication time of the current review, and looked up by name on load.
+    Times are written as milliseconds since the Unix epoch, negative for times before it.
+*/
+
+use super::{to_millis, Post, PostState, Revision, Transition};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const HEADER: &str = "post v1";
+const EXTENSION: &str = "post";
+
+const ACTIONS: [&str; 5] = ["request_review", "approve", "reject", "schedule", "archive"];
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    InvalidId(String),
+    NotFound(String),
+    Malformed(String),
+    UnknownState(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "I/O error: {e}"),
+            StoreError::InvalidId(id) => write!(f, "'{id}' is not a valid post id"),
+            StoreError::NotFound(id) => write!(f, "no post is stored with id '{id}'"),
+            StoreError::Malformed(reason) => write!(f, "malformed post file: {reason}"),
+            StoreError::UnknownState(tag) => write!(f, "unknown post state '{tag}'"),
+        }
+    }
+}
+
+impl Error for StoreError {}
+
+/*
+    Implementing From lets the ? operator convert io::Error into a StoreError for us.
+*/
+impl From<io::Error> for StoreError {
+    fn from(error: io::Error) -> StoreError {
+        StoreError::Io(error)
+    }
+}
+
+pub struct PostStore {
+    dir: PathBuf,
+}
+
+impl PostStore {
+    /*
+        Opens the store in dir, creating the directory if it doesn't exist yet.
+    */
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<PostStore, StoreError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(PostStore { dir })
+    }
+
+    /*
+        The post is first written to a temporary file which is then renamed over the
+        old one, so a crash half way through a save never leaves a truncated post behind.
+    */
+    pub fn save(&self, id: &str, post: &Post) -> Result<(), StoreError> {
+        let path = self.path_for(id)?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, encode(post))?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    pub fn load(&self, id: &str) -> Result<Post, StoreError> {
+        let path = self.path_for(id)?;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(StoreError::NotFound(String::from(id)))
+            }
+            Err(e) => return Err(StoreError::Io(e)),
+        };
+        decode(&text)
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), StoreError> {
+        let path = self.path_for(id)?;
+        match fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(StoreError::NotFound(String::from(id)))
+            }
+            Err(e) => Err(StoreError::Io(e)),
+        }
+    }
+
+    /*
+        Returns the ids of all stored posts in sorted order.
+    */
+    pub fn ids(&self) -> Result<Vec<String>, StoreError> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    ids.push(String::from(stem));
+                }
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /*
+        Ids become file names, so we only accept characters that can't escape the
+        store directory (no slashes or dots).
+    */
+    fn path_for(&self, id: &str) -> Result<PathBuf, StoreError> {
+        let valid = !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(StoreError::InvalidId(String::from(id)));
+        }
+        Ok(self.dir.join(format!("{id}.{EXTENSION}")))
+    }
+}
+
+fn encode(post: &Post) -> String {
+    let mut lines = vec![
+        String::from(HEADER),
+        format!("author {}", escape(&post.author)),
+        format!("required_approvals {}", post.required_approvals),
+        format!("state {}", post.state.name()),
+    ];
+    for approver in post.approvals() {
+        lines.push(format!("approval {}", escape(approver)));
+    }
+    if let Some(publish_at) = post.publish_at() {
+        lines.push(format!("publish_at {}", to_millis(publish_at)));
+    }
+    for revision in &post.revisions {
+        lines.push(format!("revision {}", escape(&revision.content)));
+    }
+    for transition in &post.audit_log {
+        lines.push(format!(
+            "transition {} {} {} {} {}",
+            transition.action,
+            transition.from,
+            transition.to,
+            to_millis(transition.timestamp),
+            escape(&transition.actor)
+        ));
+    }
+
+    let mut text = lines.join("\n");
+    text.push('\n');
+    text
+}
+
+fn decode(text: &str) -> Result<Post, StoreError> {
+    let mut lines = text.lines();
+    if lines.next() != Some(HEADER) {
+        return Err(malformed("missing header"));
+    }
+
+    let mut author = None;
+    let mut required_approvals = None;
+    let mut tag = None;
+    let mut approvals = Vec::new();
+    let mut publish_at = None;
+    let mut revisions = Vec::new();
+    let mut audit_log = Vec::new();
+
+    for line in lines {
+        let (key, value) = line
+            .split_once(' ')
+            .ok_or_else(|| malformed(&format!("expected a key and a value in '{line}'")))?;
+        match key {
+            "author" => author = Some(unescape(value)?),
+            "required_approvals" => {
+                required_approvals = Some(
+                    value
+                        .parse()
+                        .map_err(|_| malformed("required_approvals is not a number"))?,
+                )
+            }
+            "state" => tag = Some(value),
+            "approval" => approvals.push(unescape(value)?),
+            "publish_at" => publish_at = Some(from_millis(value)?),
+            "revision" => revisions.push(Revision {
+                number: revisions.len(),
+                content: unescape(value)?,
+            }),
+            "transition" => audit_log.push(decode_transition(value)?),
+            _ => return Err(malformed(&format!("unknown key '{key}'"))),
+        }
+    }
+
+    let author = author.ok_or_else(|| malformed("missing author"))?;
+    let required_approvals =
+        required_approvals.ok_or_else(|| malformed("missing required_approvals"))?;
+    let tag = tag.ok_or_else(|| malformed("missing state"))?;
+    let content = match revisions.last() {
+        Some(revision) => revision.content.clone(),
+        None => return Err(malformed("missing revisions")),
+    };
+
+    let state = restore_state(tag, publish_at)?;
+    Ok(Post {
+        state,
+        content,
+        author,
+        required_approvals,
+        approvals,
+        publish_at,
+        revisions,
+        audit_log,
+    })
+}
+
+/*
+    Turns a saved tag back into the state it names. A scheduled post can't be shown
+    without knowing when, so it must come with its publication time.
+*/
+fn restore_state(tag: &str, publish_at: Option<SystemTime>) -> Result<PostState, StoreError> {
+    match PostState::from_name(tag) {
+        Some(PostState::Scheduled) if publish_at.is_none() => {
+            Err(malformed("scheduled post without publish_at"))
+        }
+        Some(state) => Ok(state),
+        None => Err(StoreError::UnknownState(String::from(tag))),
+    }
+}
+
+fn decode_transition(value: &str) -> Result<Transition, StoreError> {
+    let mut fields = value.splitn(5, ' ');
+    let mut next = || {
+        fields
+            .next()
+            .ok_or_else(|| malformed("incomplete transition"))
+    };
+
+    let action =
+        known_name(next()?, &ACTIONS).ok_or_else(|| malformed("unknown transition action"))?;
+    let from = state_name(next()?)?;
+    let to = state_name(next()?)?;
+    let timestamp = from_millis(next()?)?;
+    let actor = unescape(next()?)?;
+
+    Ok(Transition {
+        action,
+        from,
+        to,
+        actor,
+        timestamp,
+    })
+}
+
+/*
+    Transition stores &'static str names, so instead of leaking the strings we read,
+    we look up the matching constant.
+*/
+fn known_name(name: &str, names: &[&'static str]) -> Option<&'static str> {
+    names.iter().copied().find(|known| *known == name)
+}
+
+fn state_name(name: &str) -> Result<&'static str, StoreError> {
+    PostState::from_name(name)
+        .map(|state| state.name())
+        .ok_or_else(|| StoreError::UnknownState(String::from(name)))
+}
+
+fn from_millis(value: &str) -> Result<SystemTime, StoreError> {
+    let millis: i128 = value
+        .parse()
+        .map_err(|_| malformed("timestamp is not a number"))?;
+    let distance = u64::try_from(millis.unsigned_abs())
+        .map(Duration::from_millis)
+        .map_err(|_| malformed("timestamp is out of range"))?;
+    let time = if millis < 0 {
+        UNIX_EPOCH.checked_sub(distance)
+    } else {
+        UNIX_EPOCH.checked_add(distance)
+    };
+    time.ok_or_else(|| malformed("timestamp is out of range"))
+}
+
+fn malformed(reason: &str) -> StoreError {
+    StoreError::Malformed(String::from(reason))
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> Result<String, StoreError> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            _ => return Err(malformed("invalid escape sequence")),
+        }
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /*
+        Each test gets its own directory so tests can run in parallel.
+    */
+    struct TempStore {
+        store: PostStore,
+        dir: PathBuf,
+    }
+
+    impl TempStore {
+        fn new(name: &str) -> TempStore {
+            let dir = std::env::temp_dir().join(format!("poststore-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TempStore {
+                store: PostStore::open(&dir).unwrap(),
+                dir,
+            }
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn round_trip(post: &Post) -> Post {
+        decode(&encode(post)).unwrap()
+    }
+
+    #[test]
+    fn draft_round_trips_with_revisions() {
+        let mut post = Post::with_required_approvals("alice", 3);
+        post.add_text("line one\n").unwrap();
+        post.add_text("back\\slash\r\n").unwrap();
+
+        let loaded = round_trip(&post);
+        assert_eq!(loaded.state_name(), "draft");
+        assert_eq!(loaded.author(), "alice");
+        assert_eq!(loaded.required_approvals, 3);
+        assert_eq!(loaded.revisions(), post.revisions());
+        assert_eq!(loaded.content, "line one\nback\\slash\r\n");
+    }
+
+    #[test]
+    fn pending_review_keeps_approvals_and_publish_time() {
+        let publish_at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let mut post = Post::new("alice");
+        post.add_text("Hello").unwrap();
+        post.request_review().unwrap();
+        post.schedule("bob", publish_at).unwrap();
+        post.approve("bob").unwrap();
+
+        let mut loaded = round_trip(&post);
+        assert_eq!(loaded.state_name(), "pending_review");
+        assert_eq!(loaded.approvals(), ["bob"]);
+
+        loaded.approve("carol").unwrap();
+        assert_eq!(loaded.state_name(), "scheduled");
+        assert_eq!(loaded.publish_at(), Some(publish_at));
+    }
+
+    #[test]
+    fn published_and_archived_restore_visibility() {
+        let mut post = Post::with_required_approvals("alice", 1);
+        post.add_text("Hello").unwrap();
+        post.request_review().unwrap();
+        post.approve("bob").unwrap();
+        assert_eq!(round_trip(&post).content(), "Hello");
+
+        post.archive("alice").unwrap();
+        let loaded = round_trip(&post);
+        assert_eq!(loaded.state_name(), "archived");
+        assert_eq!(loaded.content(), "");
+    }
+
+    #[test]
+    fn audit_log_round_trips_to_the_millisecond() {
+        let mut post = Post::with_required_approvals("alice", 1);
+        post.request_review().unwrap();
+        post.approve("bob\nthe reviewer").unwrap();
+
+        let loaded = round_trip(&post);
+        let original: Vec<String> = post.audit_log().map(|t| t.to_json()).collect();
+        let restored: Vec<String> = loaded.audit_log().map(|t| t.to_json()).collect();
+        assert_eq!(original, restored);
+    }
+
+    #[test]
+    fn times_before_the_epoch_round_trip() {
+        let publish_at = UNIX_EPOCH - Duration::from_millis(86_400_123);
+        let mut post = Post::new("alice");
+        post.request_review().unwrap();
+        post.schedule("bob", publish_at).unwrap();
+
+        let text = encode(&post);
+        assert!(text.contains("publish_at -86400123\n"));
+        assert_eq!(decode(&text).unwrap().publish_at(), Some(publish_at));
+
+        for time in [UNIX_EPOCH, publish_at, UNIX_EPOCH + Duration::from_millis(1)] {
+            assert_eq!(from_millis(&to_millis(time).to_string()).unwrap(), time);
+        }
+    }
+
+    #[test]
+    fn unrepresentable_times_are_rejected() {
+        let too_far = format!("{}", i128::MAX);
+        assert!(matches!(from_millis(&too_far), Err(StoreError::Malformed(_))));
+        assert!(matches!(from_millis("-1.5"), Err(StoreError::Malformed(_))));
+    }
+
+    #[test]
+    fn unknown_state_tag_is_rejected() {
+        let text = encode(&Post::new("alice")).replace("state draft", "state deleted");
+        assert!(matches!(decode(&text), Err(StoreError::UnknownState(tag)) if tag == "deleted"));
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        assert!(matches!(
+            decode("not a post\n"),
+            Err(StoreError::Malformed(_))
+        ));
+
+        let text = encode(&Post::new("alice")).replace("revision ", "revision \\x");
+        assert!(matches!(decode(&text), Err(StoreError::Malformed(_))));
+    }
+
+    #[test]
+    fn store_survives_reopening() {
+        let temp = TempStore::new("reopen");
+        let mut post = Post::new("alice");
+        post.add_text("unfinished draft").unwrap();
+        temp.store.save("first-post", &post).unwrap();
+
+        let reopened = PostStore::open(&temp.dir).unwrap();
+        let mut loaded = reopened.load("first-post").unwrap();
+        assert_eq!(reopened.ids().unwrap(), ["first-post"]);
+
+        loaded.add_text(", now finished").unwrap();
+        reopened.save("first-post", &loaded).unwrap();
+        assert_eq!(temp.store.load("first-post").unwrap().revisions().len(), 3);
+    }
+
+    #[test]
+    fn store_rejects_unsafe_ids_and_reports_missing_posts() {
+        let temp = TempStore::new("ids");
+        let post = Post::new("alice");
+        assert!(matches!(
+            temp.store.save("../escape", &post),
+            Err(StoreError::InvalidId(_))
+        ));
+        assert!(matches!(
+            temp.store.load("missing"),
+            Err(StoreError::NotFound(_))
+        ));
+
+        temp.store.save("a", &post).unwrap();
+        temp.store.delete("a").unwrap();
+        assert!(matches!(
+            temp.store.delete("a"),
+            Err(StoreError::NotFound(_))
+        ));
+        assert!(temp.store.ids().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,315 @@
+/*
+    The workflow! macro generates both versions of a state machine from one declarative
+    definition: the list of states, and the transitions between them, optionally guarded
+    by a function that must return true for the transition to happen.
+
+        workflow! {
+            machine: DoorState,
+            typed: Door,
+            markers: door_states,
+            data: u32,
+            states: [Closed = "closed", Open = "open", Locked = "locked"],
+            transitions: [
+                open: Closed => Open,
+                close: Open => Closed,
+                lock: Closed => Locked if has_key,
+            ],
+        }
+
+    expands to:
+
+    1. A runtime state machine: an enum with one variant per state, whose fire method
+       looks an event up in the transition table and returns the next state, or a
+       WorkflowError when the event isn't allowed in the current state. Each state has
+       a name, used for it in messages and files, and from_name turns it back into
+       the state.
+
+    2. Compile-time type states: a marker type per state (inside the markers module) and
+       a wrapper struct generic over the marker, Door<door_states::Closed> for example.
+       Each transition becomes a method that consumes the wrapper and returns the wrapper
+       for the next state, so calling a transition in the wrong state doesn't compile.
+       A guarded transition returns Result, giving the original value back when the
+       guard fails.
+
+    The first state listed is the initial one: the wrapper for it gets a start constructor.
+    Guards receive a reference to the data carried by the wrapper. Since each transition
+    becomes a method on the source state's wrapper, an event can only leave a given
+    state through one transition. The same event may leave different states, though.
+
+    The macro is only meant for this crate, so it isn't exported; other modules bring
+    it into scope with use crate::designpattern::workflow::workflow.
+*/
+
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum WorkflowError {
+    InvalidTransition {
+        event: String,
+        state: &'static str,
+    },
+    GuardFailed {
+        event: &'static str,
+        state: &'static str,
+    },
+}
+
+impl fmt::Display for WorkflowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorkflowError::InvalidTransition { event, state } => {
+                write!(f, "{event} is not allowed in the {state} state")
+            }
+            WorkflowError::GuardFailed { event, state } => {
+                write!(f, "the guard for {event} rejected the transition from {state}")
+            }
+        }
+    }
+}
+
+/*
+    macro_rules! macros match the invocation against each rule in order. The rules
+    starting with @ are internal helpers the main rule calls once per transition, since
+    guarded and unguarded transitions generate different methods. They are called
+    through their full path, so the macro works wherever it is invoked from.
+*/
+macro_rules! workflow {
+    (
+        machine: $machine:ident,
+        typed: $typed:ident,
+        markers: $markers:ident,
+        data: $data:ty,
+        states: [$initial:ident = $initial_name:literal $(, $state:ident = $name:literal)* $(,)?],
+        transitions: [
+            $($event:ident : $from:ident => $to:ident $(if $guard:path)?),+ $(,)?
+        ] $(,)?
+    ) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $machine {
+            $initial,
+            $($state),*
+        }
+
+        impl $machine {
+            pub const INITIAL: $machine = $machine::$initial;
+
+            pub const ALL: &'static [$machine] = &[$machine::$initial, $($machine::$state),*];
+
+            pub const TRANSITIONS: &'static [(&'static str, $machine, $machine)] = &[
+                $((stringify!($event), $machine::$from, $machine::$to)),+
+            ];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $machine::$initial => $initial_name,
+                    $($machine::$state => $name),*
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<$machine> {
+                $machine::ALL.iter().copied().find(|state| state.name() == name)
+            }
+
+            pub fn fire(
+                self,
+                event: &str,
+                data: &$data,
+            ) -> Result<$machine, $crate::designpattern::workflow::WorkflowError> {
+                let _ = data; // unused when no transition has a guard
+                $(
+                    if self == $machine::$from && event == stringify!($event) {
+                        $(
+                            if !$guard(data) {
+                                return Err($crate::designpattern::workflow::WorkflowError::GuardFailed {
+                                    event: stringify!($event),
+                                    state: self.name(),
+                                });
+                            }
+                        )?
+                        return Ok($machine::$to);
+                    }
+                )+
+                Err($crate::designpattern::workflow::WorkflowError::InvalidTransition {
+                    event: String::from(event),
+                    state: self.name(),
+                })
+            }
+        }
+
+        pub mod $markers {
+            pub struct $initial;
+            $(pub struct $state;)*
+        }
+
+        pub struct $typed<S> {
+            data: $data,
+            state: std::marker::PhantomData<S>,
+        }
+
+        impl<S> $typed<S> {
+            pub fn data(&self) -> &$data {
+                &self.data
+            }
+
+            pub fn into_data(self) -> $data {
+                self.data
+            }
+        }
+
+        impl $typed<$markers::$initial> {
+            pub fn start(data: $data) -> $typed<$markers::$initial> {
+                $typed {
+                    data,
+                    state: std::marker::PhantomData,
+                }
+            }
+        }
+
+        $crate::designpattern::workflow::workflow!(@state $machine, $typed, $markers, $initial);
+        $($crate::designpattern::workflow::workflow!(@state $machine, $typed, $markers, $state);)*
+
+        $(
+            $crate::designpattern::workflow::workflow!(
+                @transition $typed, $markers, $event, $from, $to $(, $guard)?
+            );
+        )+
+    };
+
+    (@state $machine:ident, $typed:ident, $markers:ident, $state:ident) => {
+        impl $typed<$markers::$state> {
+            /*
+                Lets code holding a type state hand it over to the runtime machine.
+            */
+            pub const STATE: $machine = $machine::$state;
+        }
+    };
+
+    (@transition $typed:ident, $markers:ident, $event:ident, $from:ident, $to:ident) => {
+        impl $typed<$markers::$from> {
+            pub fn $event(self) -> $typed<$markers::$to> {
+                $typed {
+                    data: self.data,
+                    state: std::marker::PhantomData,
+                }
+            }
+        }
+    };
+
+    (@transition $typed:ident, $markers:ident, $event:ident, $from:ident, $to:ident, $guard:path) => {
+        impl $typed<$markers::$from> {
+            pub fn $event(self) -> Result<$typed<$markers::$to>, $typed<$markers::$from>> {
+                if $guard(&self.data) {
+                    Ok($typed {
+                        data: self.data,
+                        state: std::marker::PhantomData,
+                    })
+                } else {
+                    Err(self)
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use workflow;
+
+#[cfg(test)]
+mod tests {
+    use super::WorkflowError;
+
+    fn has_key(keys: &u32) -> bool {
+        *keys > 0
+    }
+
+    workflow! {
+        machine: DoorState,
+        typed: Door,
+        markers: door_states,
+        data: u32,
+        states: [Closed = "closed", Open = "open", Locked = "locked"],
+        transitions: [
+            open: Closed => Open,
+            close: Open => Closed,
+            lock: Closed => Locked if has_key,
+            unlock: Locked => Closed if has_key,
+        ],
+    }
+
+    #[test]
+    fn runtime_machine_follows_the_transition_table() {
+        let state = DoorState::INITIAL;
+        assert_eq!(state, DoorState::Closed);
+
+        let state = state.fire("open", &0).unwrap();
+        assert_eq!(state, DoorState::Open);
+        assert_eq!(state.fire("close", &0), Ok(DoorState::Closed));
+    }
+
+    #[test]
+    fn runtime_machine_rejects_unknown_events_for_a_state() {
+        assert_eq!(
+            DoorState::Open.fire("lock", &1),
+            Err(WorkflowError::InvalidTransition {
+                event: String::from("lock"),
+                state: "open",
+            })
+        );
+        assert!(DoorState::Closed.fire("fly", &1).is_err());
+    }
+
+    #[test]
+    fn runtime_machine_checks_guards() {
+        assert_eq!(DoorState::Closed.fire("lock", &1), Ok(DoorState::Locked));
+        assert_eq!(
+            DoorState::Closed.fire("lock", &0),
+            Err(WorkflowError::GuardFailed {
+                event: "lock",
+                state: "closed",
+            })
+        );
+    }
+
+    #[test]
+    fn states_are_found_by_name() {
+        assert_eq!(DoorState::ALL.len(), 3);
+        assert_eq!(DoorState::Locked.name(), "locked");
+        assert_eq!(DoorState::from_name("open"), Some(DoorState::Open));
+        assert_eq!(DoorState::from_name("Open"), None);
+    }
+
+    #[test]
+    fn transition_table_lists_every_transition() {
+        assert_eq!(DoorState::TRANSITIONS.len(), 4);
+        assert_eq!(
+            DoorState::TRANSITIONS[2],
+            ("lock", DoorState::Closed, DoorState::Locked)
+        );
+    }
+
+    #[test]
+    fn type_states_mirror_the_runtime_machine() {
+        let door = Door::start(1);
+        assert_eq!(Door::<door_states::Closed>::STATE, DoorState::Closed);
+
+        let door = door.open();
+        assert_eq!(Door::<door_states::Open>::STATE, DoorState::Open);
+
+        let door = door.close();
+        let door = door.lock().ok().unwrap();
+        assert_eq!(Door::<door_states::Locked>::STATE, DoorState::Locked);
+
+        let door = door.unlock().ok().unwrap();
+        assert_eq!(door.into_data(), 1);
+    }
+
+    #[test]
+    fn failed_guards_return_the_original_type_state() {
+        let door = Door::start(0);
+        let door = match door.lock() {
+            Ok(_) => panic!("locked without a key"),
+            Err(door) => door,
+        };
+        assert_eq!(*door.data(), 0);
+        assert!(door.open().close().lock().is_err());
+    }
+}
@@ -0,0 +1,6 @@
+/*
+    The examples of chapter 17 are modules of one library crate, so cargo build keeps them
+    compiling and cargo test runs their tests.
+*/
+pub mod designpattern;
+pub mod rustoop;
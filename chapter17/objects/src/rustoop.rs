@@ -13,21 +13,61 @@
     The following struct is public, so it can be used by other modules, but its fields
     are private, allowing manipulation only through the public methods below.
 */
-pub struct AveragedCollection {
-    list: Vec<i32>,
-    average: f64,
+pub struct AveragedCollection<T: Sample = i32> {
+    list: Vec<T>,
+    average: Option<f64>,
+    totals: T::Accumulator,
+    extremes: Vec<(T, T)>,
+    median: RunningMedian<T>,
 }
 
-impl AveragedCollection {
-    pub fn add(&mut self, value: i32) {
+/*
+    Because the fields are private, add and remove are the only ways to change the list,
+    so they can keep every cached statistic consistent with it. Each one is updated from
+    the value that came or went, without walking through the list again:
+
+    - the accumulator chosen by the Sample type keeps the totals behind the mean and
+      the variance, in a form that can't overflow like an i32 sum of i32 values would.
+    - remove always takes the last value, so next to each value we store the minimum
+      and maximum of the list up to it, and popping it brings back the previous ones.
+    - the median needs a structure of its own, see RunningMedian in stats.rs.
+
+    The collection works with any type implementing Sample (see stats.rs), and defaults
+    to i32 as it did before it was generic.
+*/
+impl<T: Sample> AveragedCollection<T> {
+    pub fn new() -> AveragedCollection<T> {
+        AveragedCollection {
+            list: Vec::new(),
+            average: None,
+            totals: T::Accumulator::default(),
+            extremes: Vec::new(),
+            median: RunningMedian::new(),
+        }
+    }
+
+    pub fn add(&mut self, value: T) {
         self.list.push(value);
+        self.totals.add(value);
+        let extremes = match self.extremes.last() {
+            Some(&(min, max)) => (
+                if value.key() < min.key() { value } else { min },
+                if value.key() > max.key() { value } else { max },
+            ),
+            None => (value, value),
+        };
+        self.extremes.push(extremes);
+        self.median.insert(value);
         self.update_average();
     }
 
-    pub fn remove(&mut self) -> Option<i32> {
+    pub fn remove(&mut self) -> Option<T> {
         let result = self.list.pop();
         match result {
             Some(value) => {
+                self.totals.remove(value);
+                self.extremes.pop();
+                self.median.remove(value);
                 self.update_average();
                 Some(value)
             }
@@ -35,13 +75,53 @@ impl AveragedCollection {
         }
     }
 
-    pub fn average(&self) -> f64 {
+    /*
+        An empty collection has no average, rather than the NaN that dividing
+        by a length of zero would give.
+    */
+    pub fn average(&self) -> Option<f64> {
         self.average
     }
 
+    /*
+        The population variance: the mean of the squared distances from the mean.
+    */
+    pub fn variance(&self) -> Option<f64> {
+        self.totals.variance()
+    }
+
+    pub fn standard_deviation(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+
+    pub fn min(&self) -> Option<T> {
+        self.extremes.last().map(|&(min, _)| min)
+    }
+
+    pub fn max(&self) -> Option<T> {
+        self.extremes.last().map(|&(_, max)| max)
+    }
+
+    pub fn median(&self) -> Option<f64> {
+        self.median.median()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
     fn update_average(&mut self) {
-        let total: i32 = self.list.iter().sum();
-        self.average = total as f64 / self.list.len() as f64;
+        self.average = self.totals.mean();
+    }
+}
+
+impl<T: Sample> Default for AveragedCollection<T> {
+    fn default() -> AveragedCollection<T> {
+        AveragedCollection::new()
     }
 }
 
@@ -64,18 +144,326 @@ impl AveragedCollection {
     the dyn keyword, and then including the relevant trait.
 */
 
+pub mod canvas;
+pub mod components;
+pub mod events;
+pub mod layout;
+pub mod moving;
+pub mod shared;
+pub mod snapshot;
+pub mod stats;
+
+use canvas::{Canvas, Rect};
+use events::{terminal_size, Event, EventResult, InputDecoder, Key, RawMode};
+use stats::{Accumulator, RunningMedian, Sample};
+use std::io::{self, Read, Write};
+
+/*
+    Components draw themselves into a Canvas of character cells, inside the area
+    they are given. Passing the area in, rather than storing it in each component,
+    lets whoever owns the component decide where it goes.
+*/
 pub trait Draw {
-    fn draw(&self);
+    fn draw(&self, canvas: &mut Canvas, area: Rect);
+}
+
+/*
+    Interactive components extend Draw. Because Draw is a supertrait, anything that
+    implements Component can also be drawn, and a Box<dyn Component> gives access to
+    both sets of methods. The defaults describe a component that ignores input,
+    so a Label only needs an empty impl block.
+*/
+pub trait Component: Draw {
+    fn focusable(&self) -> bool {
+        false
+    }
+    fn set_focused(&mut self, _focused: bool) {}
+    /*
+        Containers move the focus between their own children before letting it leave.
+        Called after set_focus(true) when the focus arrives, and on each tab after that,
+        it returns false once the focus should move on to the next component. A
+        component without children has nowhere to move, so the default says so.
+    */
+    fn focus_step(&mut self, _forwards: bool) -> bool {
+        false
+    }
+    /*
+        Key events only reach the focused component; clicks reach the component
+        under the mouse. The area is the one the component was last drawn in.
+    */
+    fn handle_event(&mut self, _event: &Event, _area: Rect) -> EventResult {
+        EventResult::Ignored
+    }
+}
+
+/*
+    Positions a component within the area its parent gives it. The area of a Placed
+    is relative to the parent's top left corner and is cut down to fit inside it.
+*/
+pub struct Placed {
+    pub area: Rect,
+    pub component: Box<dyn Component>,
+}
+
+impl Placed {
+    fn absolute_area(&self, parent: Rect) -> Rect {
+        let x = parent.x + self.area.x;
+        let y = parent.y + self.area.y;
+        let width = self.area.width.min(parent.right().saturating_sub(x));
+        let height = self.area.height.min(parent.bottom().saturating_sub(y));
+        Rect::new(x, y, width, height)
+    }
+}
+
+impl Draw for Placed {
+    fn draw(&self, canvas: &mut Canvas, area: Rect) {
+        self.component.draw(canvas, self.absolute_area(area));
+    }
+}
+
+impl Component for Placed {
+    fn focusable(&self) -> bool {
+        self.component.focusable()
+    }
+    fn set_focused(&mut self, focused: bool) {
+        self.component.set_focused(focused);
+    }
+    fn focus_step(&mut self, forwards: bool) -> bool {
+        self.component.focus_step(forwards)
+    }
+    fn handle_event(&mut self, event: &Event, area: Rect) -> EventResult {
+        let area = self.absolute_area(area);
+        match event {
+            Event::Click { x, y } if !area.contains(*x, *y) => EventResult::Ignored,
+            _ => self.component.handle_event(event, area),
+        }
+    }
 }
 
 pub struct Screen {
-    pub components: Vec<Box<dyn Draw>>,
+    pub width: usize,
+    pub height: usize,
+    pub components: Vec<Box<dyn Component>>,
+    focus: Option<usize>,
+    previous: Option<Canvas>,
 }
 
 impl Screen {
-    pub fn run(&self) {
+    pub fn new(width: usize, height: usize) -> Screen {
+        Screen {
+            width,
+            height,
+            components: Vec::new(),
+            focus: None,
+            previous: None,
+        }
+    }
+
+    /*
+        Adds a component at a fixed position on the screen.
+    */
+    pub fn add<C: Component + 'static>(&mut self, area: Rect, component: C) {
+        self.components.push(Box::new(Placed {
+            area,
+            component: Box::new(component),
+        }));
+    }
+
+    /*
+        Adds a component that takes up the whole screen, usually a layout container
+        holding everything else. It follows the screen when it is resized.
+    */
+    pub fn add_full<C: Component + 'static>(&mut self, component: C) {
+        self.components.push(Box::new(component));
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+    }
+
+    /*
+        Composes a frame by letting every component draw into one canvas, in order,
+        so later components are drawn on top of earlier ones.
+    */
+    pub fn render(&self) -> Canvas {
+        let mut canvas = Canvas::new(self.width, self.height);
+        let area = canvas.area();
         for component in self.components.iter() {
-            component.draw();
+            component.draw(&mut canvas, area);
+        }
+        canvas
+    }
+
+    /*
+        Writes the frame to the terminal with ANSI escape sequences. The first frame
+        clears the terminal (ESC [ 2J) and is written whole; after that the screen
+        remembers what it sent last and only writes the cells that changed.
+    */
+    pub fn flush_to<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let frame = self.render();
+        match &self.previous {
+            Some(previous) => frame.write_diff(previous, out)?,
+            None => {
+                write!(out, "\x1b[2J")?;
+                frame.write_ansi(out)?;
+            }
+        }
+        self.previous = Some(frame);
+        Ok(())
+    }
+
+    /*
+        Forgets the previous frame, so the next flush redraws the whole terminal. Needed
+        when something else may have written to the terminal in between.
+    */
+    pub fn invalidate(&mut self) {
+        self.previous = None;
+    }
+
+    pub fn focused(&self) -> Option<usize> {
+        self.focus
+    }
+
+    /*
+        Tab moves the focus forwards and shift + tab backwards, skipping components
+        that can't take focus and wrapping around at either end.
+    */
+    pub fn focus_next(&mut self) {
+        self.move_focus(true);
+    }
+
+    pub fn focus_previous(&mut self) {
+        self.move_focus(false);
+    }
+
+    fn move_focus(&mut self, forwards: bool) {
+        let count = self.components.len();
+        if count == 0 {
+            return;
+        }
+        if let Some(i) = self.focus {
+            if self.components[i].focus_step(forwards) {
+                return;
+            }
+        }
+        let start = match (self.focus, forwards) {
+            (Some(i), true) => i + 1,
+            (Some(i), false) => i + count - 1,
+            (None, true) => 0,
+            (None, false) => count - 1,
+        };
+        let next = (0..count)
+            .map(|step| {
+                if forwards {
+                    (start + step) % count
+                } else {
+                    (start + count - step) % count
+                }
+            })
+            .find(|&i| self.components[i].focusable());
+        if let Some(next) = next {
+            self.set_focus(Some(next));
+            self.components[next].focus_step(forwards);
+        }
+    }
+
+    fn set_focus(&mut self, focus: Option<usize>) {
+        if focus == self.focus {
+            return;
+        }
+        if let Some(old) = self.focus {
+            self.components[old].set_focused(false);
+        }
+        if let Some(new) = focus {
+            self.components[new].set_focused(true);
+        }
+        self.focus = focus;
+    }
+
+    /*
+        Routes one event to the right component. A click goes to the topmost component
+        under the mouse (the last one drawn) and moves the focus to it.
+    */
+    pub fn dispatch(&mut self, event: &Event) -> EventResult {
+        let area = Rect::new(0, 0, self.width, self.height);
+        match event {
+            Event::Key(Key::Tab) => {
+                self.focus_next();
+                EventResult::Handled
+            }
+            Event::Key(Key::BackTab) => {
+                self.focus_previous();
+                EventResult::Handled
+            }
+            Event::Key(_) => match self.focus {
+                Some(i) => self.components[i].handle_event(event, area),
+                None => EventResult::Ignored,
+            },
+            Event::Click { .. } => {
+                for i in (0..self.components.len()).rev() {
+                    if self.components[i].handle_event(event, area) == EventResult::Handled {
+                        if self.components[i].focusable() {
+                            self.set_focus(Some(i));
+                        }
+                        return EventResult::Handled;
+                    }
+                }
+                EventResult::Ignored
+            }
+        }
+    }
+
+    /*
+        Runs the interactive event loop on the terminal until Escape or ctrl + c is pressed.
+        The screen takes the size of the terminal, which is checked again before each
+        frame. Without signal handling we only notice a resize on the next input event.
+    */
+    pub fn run(&mut self) -> io::Result<()> {
+        let _raw_mode = RawMode::enable()?;
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        self.event_loop(stdin.lock(), stdout.lock(), || terminal_size().ok())
+    }
+
+    /*
+        The event loop itself: draw a frame, wait for input, dispatch the events and
+        draw again. It also stops when the input ends. Taking the input and output as
+        parameters lets tests drive the loop without a terminal.
+    */
+    pub fn run_with<R: Read, W: Write>(&mut self, input: R, output: W) -> io::Result<()> {
+        self.event_loop(input, output, || None)
+    }
+
+    fn event_loop<R, W, F>(&mut self, mut input: R, mut output: W, mut size: F) -> io::Result<()>
+    where
+        R: Read,
+        W: Write,
+        F: FnMut() -> Option<(usize, usize)>,
+    {
+        if self.focus.is_none() {
+            self.focus_next();
+        }
+        self.invalidate();
+        let mut decoder = InputDecoder::new();
+        let mut buffer = [0; 64];
+
+        loop {
+            if let Some((width, height)) = size() {
+                self.resize(width, height);
+            }
+            self.flush_to(&mut output)?;
+
+            let read = input.read(&mut buffer)?;
+            if read == 0 {
+                return Ok(());
+            }
+            for event in decoder.decode(&buffer[..read]) {
+                if let Event::Key(Key::Escape) | Event::Key(Key::Ctrl('c')) = event {
+                    return Ok(());
+                }
+                self.dispatch(&event);
+            }
         }
     }
 }
@@ -84,9 +472,12 @@ impl Screen {
     This is better than defining a struct that uses a generic type parameter with trait bounds such
     a generic type parameter can be substituted with only one concrete type at runtime, whereas
     trait objects allow for multiple concrete types to fill in for the trait object at runtime.
+
+    The book calls the generic version Screen too; it is GenericScreen here, so both can live in
+    the same module.
 */
 
-pub struct Screen<T: Draw> {
+pub struct GenericScreen<T: Draw> {
     pub components: Vec<T>,
 }
 
@@ -0,0 +1,366 @@
+/*
+    A canvas is a grid of character cells that components draw into. Drawing is
+    clipped to the canvas, so a component placed partly off screen can't panic
+    or write past the end of a row.
+*/
+
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /*
+        The area inside a one cell wide border.
+    */
+    pub fn inner(&self) -> Rect {
+        Rect::new(
+            self.x + 1,
+            self.y + 1,
+            self.width.saturating_sub(2),
+            self.height.saturating_sub(2),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    pub bold: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    pub const PLAIN: Style = Style {
+        bold: false,
+        reverse: false,
+    };
+    pub const BOLD: Style = Style {
+        bold: true,
+        reverse: false,
+    };
+    pub const REVERSE: Style = Style {
+        bold: false,
+        reverse: true,
+    };
+
+    /*
+        The ANSI Select Graphic Rendition sequence that switches the terminal to this style.
+        It always starts with a reset (0) so the previous style doesn't leak into this one.
+    */
+    fn sgr(&self) -> String {
+        let mut codes = String::from("0");
+        if self.bold {
+            codes.push_str(";1");
+        }
+        if self.reverse {
+            codes.push_str(";7");
+        }
+        format!("\x1b[{codes}m")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+}
+
+impl Cell {
+    pub const BLANK: Cell = Cell {
+        ch: ' ',
+        style: Style::PLAIN,
+    };
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize) -> Canvas {
+        Canvas {
+            width,
+            height,
+            cells: vec![Cell::BLANK; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn area(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<&Cell> {
+        if x < self.width && y < self.height {
+            self.cells.get(y * self.width + x)
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, ch: char, style: Style) {
+        if x < self.width && y < self.height {
+            self.cells[y * self.width + x] = Cell { ch, style };
+        }
+    }
+
+    /*
+        Writes text starting at (x, y), cutting it off after max_width characters.
+        Returns how many characters were written.
+    */
+    pub fn write_str(
+        &mut self,
+        x: usize,
+        y: usize,
+        text: &str,
+        max_width: usize,
+        style: Style,
+    ) -> usize {
+        let mut written = 0;
+        for (i, ch) in text.chars().take(max_width).enumerate() {
+            self.set(x + i, y, ch, style);
+            written += 1;
+        }
+        written
+    }
+
+    pub fn fill(&mut self, area: Rect, ch: char, style: Style) {
+        for y in area.y..area.bottom() {
+            for x in area.x..area.right() {
+                self.set(x, y, ch, style);
+            }
+        }
+    }
+
+    pub fn draw_border(&mut self, area: Rect, style: Style) {
+        if area.width < 2 || area.height < 2 {
+            return;
+        }
+        let (right, bottom) = (area.right() - 1, area.bottom() - 1);
+        for x in area.x + 1..right {
+            self.set(x, area.y, '─', style);
+            self.set(x, bottom, '─', style);
+        }
+        for y in area.y + 1..bottom {
+            self.set(area.x, y, '│', style);
+            self.set(right, y, '│', style);
+        }
+        self.set(area.x, area.y, '┌', style);
+        self.set(right, area.y, '┐', style);
+        self.set(area.x, bottom, '└', style);
+        self.set(right, bottom, '┘', style);
+    }
+
+    /*
+        The characters of the canvas as plain text, one line per row, ignoring styles.
+    */
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((self.width + 1) * self.height);
+        for row in self.cells.chunks(self.width.max(1)).take(self.height) {
+            text.extend(row.iter().map(|cell| cell.ch));
+            text.push('\n');
+        }
+        text
+    }
+
+    /*
+        Writes the whole canvas to a terminal: each row starts with a cursor move
+        (ESC [ row ; column H, counted from 1), and a style sequence is only emitted
+        when the style changes from the previous cell.
+    */
+    pub fn write_ansi<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut current = Style::PLAIN;
+        write!(out, "{}", current.sgr())?;
+        for y in 0..self.height {
+            write!(out, "\x1b[{};1H", y + 1)?;
+            for x in 0..self.width {
+                let cell = self.cells[y * self.width + x];
+                if cell.style != current {
+                    current = cell.style;
+                    write!(out, "{}", current.sgr())?;
+                }
+                write!(out, "{}", cell.ch)?;
+            }
+        }
+        write!(out, "{}", Style::PLAIN.sgr())?;
+        out.flush()
+    }
+
+    /*
+        Writes only the cells that changed since the previous frame. The cursor moves
+        by itself after each character, so a cursor move is only needed where the
+        changes are not next to each other, and a style sequence only where the style
+        changes. If the sizes differ the previous frame is useless, and the whole
+        canvas is written after clearing the terminal.
+    */
+    pub fn write_diff<W: Write>(&self, previous: &Canvas, out: &mut W) -> io::Result<()> {
+        if self.width != previous.width || self.height != previous.height {
+            write!(out, "\x1b[2J")?;
+            return self.write_ansi(out);
+        }
+
+        let mut cursor: Option<(usize, usize)> = None;
+        let mut current: Option<Style> = None;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = self.cells[y * self.width + x];
+                if cell == previous.cells[y * self.width + x] {
+                    continue;
+                }
+                match cursor {
+                    Some(position) if position == (x, y) => {}
+                    Some((from, row)) if row == y => match self.bridge(y, from, x, current) {
+                        Some(text) => write!(out, "{text}")?,
+                        None => write!(out, "\x1b[{};{}H", y + 1, x + 1)?,
+                    },
+                    _ => write!(out, "\x1b[{};{}H", y + 1, x + 1)?,
+                }
+                if current != Some(cell.style) {
+                    current = Some(cell.style);
+                    write!(out, "{}", cell.style.sgr())?;
+                }
+                write!(out, "{}", cell.ch)?;
+                // After the last column terminals differ on where the cursor is.
+                cursor = (x + 1 < self.width).then_some((x + 1, y));
+            }
+        }
+        if current.is_some() {
+            write!(out, "{}", Style::PLAIN.sgr())?;
+        }
+        out.flush()
+    }
+
+    /*
+        To get from the cursor to the next change on the same row we can also write
+        the unchanged cells in between again. That is cheaper than a cursor move when
+        there are only a few of them and they already have the current style.
+    */
+    fn bridge(&self, y: usize, from: usize, to: usize, current: Option<Style>) -> Option<String> {
+        let cells = &self.cells[y * self.width + from..y * self.width + to];
+        if cells.iter().any(|cell| Some(cell.style) != current) {
+            return None;
+        }
+        let text: String = cells.iter().map(|cell| cell.ch).collect();
+        let jump = format!("\x1b[{};{}H", y + 1, to + 1);
+        (text.len() < jump.len()).then_some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drawing_is_clipped_to_the_canvas() {
+        let mut canvas = Canvas::new(4, 2);
+        canvas.write_str(2, 0, "hello", 10, Style::PLAIN);
+        canvas.set(9, 9, 'x', Style::PLAIN);
+        canvas.fill(Rect::new(3, 1, 5, 5), '#', Style::PLAIN);
+        assert_eq!(canvas.to_text(), "  he\n   #\n");
+    }
+
+    #[test]
+    fn write_str_stops_at_max_width() {
+        let mut canvas = Canvas::new(8, 1);
+        assert_eq!(canvas.write_str(0, 0, "abcdef", 3, Style::PLAIN), 3);
+        assert_eq!(canvas.to_text(), "abc     \n");
+    }
+
+    #[test]
+    fn draws_borders() {
+        let mut canvas = Canvas::new(4, 3);
+        canvas.draw_border(canvas.area(), Style::PLAIN);
+        assert_eq!(canvas.to_text(), "┌──┐\n│  │\n└──┘\n");
+    }
+
+    #[test]
+    fn writes_ansi_rows_and_style_changes() {
+        let mut canvas = Canvas::new(2, 2);
+        canvas.set(1, 0, 'b', Style::REVERSE);
+        canvas.set(0, 1, 'c', Style::REVERSE);
+
+        let mut out = Vec::new();
+        canvas.write_ansi(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[0m\x1b[1;1H \x1b[0;7mb\x1b[2;1Hc\x1b[0m \x1b[0m"
+        );
+    }
+
+    #[test]
+    fn diff_writes_nothing_for_an_unchanged_frame() {
+        let mut canvas = Canvas::new(3, 2);
+        canvas.write_str(0, 0, "abc", 3, Style::BOLD);
+
+        let mut out = Vec::new();
+        canvas.write_diff(&canvas.clone(), &mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn diff_writes_only_changed_cells() {
+        let previous = Canvas::new(20, 3);
+        let mut canvas = previous.clone();
+        canvas.write_str(1, 0, "ab", 2, Style::PLAIN);
+        canvas.set(5, 0, 'c', Style::PLAIN);
+        canvas.set(19, 0, 'd', Style::REVERSE);
+        canvas.set(0, 2, 'e', Style::REVERSE);
+
+        let mut out = Vec::new();
+        canvas.write_diff(&previous, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            // The two blanks between b and c are rewritten instead of moving the cursor.
+            "\x1b[1;2H\x1b[0mab  c\x1b[1;20H\x1b[0;7md\x1b[3;1He\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn diff_redraws_everything_after_a_resize() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.set(0, 0, 'a', Style::PLAIN);
+
+        let mut out = Vec::new();
+        canvas.write_diff(&Canvas::new(3, 1), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[2J\x1b[0m\x1b[1;1Ha \x1b[0m"
+        );
+    }
+}
@@ -0,0 +1,372 @@
+/*
+    Components that can be placed on a Screen. Each one implements Draw, drawing
+    itself inside the area it is given and never outside of it, and Component,
+    reacting to the input events the Screen sends it.
+*/
+
+use super::canvas::{Canvas, Rect, Style};
+use super::events::{Event, EventResult, Key};
+use super::{Component, Draw};
+
+pub struct Label {
+    pub text: String,
+}
+
+impl Label {
+    pub fn new(text: &str) -> Label {
+        Label {
+            text: String::from(text),
+        }
+    }
+}
+
+impl Draw for Label {
+    /*
+        Each line of the text goes on its own row; lines that don't fit are cut off.
+    */
+    fn draw(&self, canvas: &mut Canvas, area: Rect) {
+        for (row, line) in self.text.lines().take(area.height).enumerate() {
+            canvas.write_str(area.x, area.y + row, line, area.width, Style::PLAIN);
+        }
+    }
+}
+
+impl Component for Label {}
+
+/*
+    The action is a boxed closure, like the ones we stored in chapter 13. It is
+    FnMut so that it can update whatever state it captured each time it runs.
+*/
+pub struct Button {
+    pub label: String,
+    focused: bool,
+    on_activate: Option<Box<dyn FnMut()>>,
+}
+
+impl Button {
+    pub fn new(label: &str) -> Button {
+        Button {
+            label: String::from(label),
+            focused: false,
+            on_activate: None,
+        }
+    }
+
+    /*
+        Sets the closure run when the button is clicked, or activated with enter
+        or space while it has focus.
+    */
+    pub fn on_activate<F: FnMut() + 'static>(mut self, action: F) -> Button {
+        self.on_activate = Some(Box::new(action));
+        self
+    }
+
+    pub fn activate(&mut self) {
+        if let Some(action) = self.on_activate.as_mut() {
+            action();
+        }
+    }
+}
+
+impl Draw for Button {
+    /*
+        A bordered box with the label centred inside it.
+    */
+    fn draw(&self, canvas: &mut Canvas, area: Rect) {
+        let border = if self.focused {
+            Style::REVERSE
+        } else {
+            Style::PLAIN
+        };
+        canvas.draw_border(area, border);
+        let inner = area.inner();
+        if inner.height == 0 {
+            return;
+        }
+        let label_width = self.label.chars().count().min(inner.width);
+        let x = inner.x + (inner.width - label_width) / 2;
+        let y = inner.y + (inner.height - 1) / 2;
+        canvas.write_str(x, y, &self.label, label_width, Style::BOLD);
+    }
+}
+
+impl Component for Button {
+    fn focusable(&self) -> bool {
+        true
+    }
+    fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+    fn handle_event(&mut self, event: &Event, _area: Rect) -> EventResult {
+        match event {
+            Event::Key(Key::Enter) | Event::Key(Key::Char(' ')) | Event::Click { .. } => {
+                self.activate();
+                EventResult::Handled
+            }
+            _ => EventResult::Ignored,
+        }
+    }
+}
+
+pub struct SelectBox {
+    pub options: Vec<String>,
+    pub selected: usize,
+    focused: bool,
+}
+
+impl SelectBox {
+    pub fn new(options: &[&str]) -> SelectBox {
+        SelectBox {
+            options: options.iter().map(|option| String::from(*option)).collect(),
+            selected: 0,
+            focused: false,
+        }
+    }
+}
+
+impl Draw for SelectBox {
+    /*
+        A bordered list of the options, with the selected one marked and highlighted.
+    */
+    fn draw(&self, canvas: &mut Canvas, area: Rect) {
+        let border = if self.focused {
+            Style::REVERSE
+        } else {
+            Style::PLAIN
+        };
+        canvas.draw_border(area, border);
+        let inner = area.inner();
+        for (row, option) in self.options.iter().take(inner.height).enumerate() {
+            let (marker, style) = if row == self.selected {
+                ("> ", Style::REVERSE)
+            } else {
+                ("  ", Style::PLAIN)
+            };
+            let y = inner.y + row;
+            let written = canvas.write_str(inner.x, y, marker, inner.width, style);
+            canvas.write_str(inner.x + written, y, option, inner.width - written, style);
+        }
+    }
+}
+
+impl Component for SelectBox {
+    fn focusable(&self) -> bool {
+        true
+    }
+    fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+    /*
+        The arrow keys move the selection; clicking an option selects it. Only rows
+        that show an option count: a click on the border, or on a row below the
+        options, selects nothing.
+    */
+    fn handle_event(&mut self, event: &Event, area: Rect) -> EventResult {
+        match event {
+            Event::Key(Key::Up) if self.selected > 0 => self.selected -= 1,
+            Event::Key(Key::Down) if self.selected + 1 < self.options.len() => self.selected += 1,
+            Event::Key(Key::Up) | Event::Key(Key::Down) => {}
+            Event::Click { y, .. } => {
+                let inner = area.inner();
+                let shown = self.options.len().min(inner.height);
+                let row = y.checked_sub(inner.y);
+                if let Some(row) = row.filter(|row| *row < shown) {
+                    self.selected = row;
+                }
+            }
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Handled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Screen;
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn screen_composes_components_into_a_frame() {
+        let mut screen = Screen::new(20, 6);
+        screen.add(Rect::new(0, 0, 20, 1), Label::new("Pick a size:"));
+        screen.add(Rect::new(0, 1, 10, 4), SelectBox::new(&["Small", "Medium"]));
+        screen.add(Rect::new(11, 1, 8, 3), Button::new("OK"));
+
+        assert_eq!(
+            screen.render().to_text(),
+            "Pick a size:        \n\
+             ┌────────┐ ┌──────┐ \n\
+             │> Small │ │  OK  │ \n\
+             │  Medium│ └──────┘ \n\
+             └────────┘          \n\
+             \u{20}                   \n"
+        );
+    }
+
+    #[test]
+    fn selected_option_is_highlighted() {
+        let mut select = SelectBox::new(&["a", "b"]);
+        select.selected = 1;
+        let mut canvas = Canvas::new(5, 4);
+        let area = canvas.area();
+        select.draw(&mut canvas, area);
+
+        assert_eq!(canvas.get(1, 1).unwrap().style, Style::PLAIN);
+        assert_eq!(canvas.get(1, 2).unwrap().style, Style::REVERSE);
+        assert_eq!(canvas.get(3, 2).unwrap().ch, 'b');
+    }
+
+    #[test]
+    fn clicks_only_select_options_that_are_drawn() {
+        let mut select = SelectBox::new(&["a", "b", "c", "d"]);
+        let area = Rect::new(0, 0, 5, 4); // room for two options inside the border
+
+        select.handle_event(&Event::Click { x: 2, y: 2 }, area);
+        assert_eq!(select.selected, 1);
+        select.handle_event(&Event::Click { x: 2, y: 3 }, area); // the bottom border
+        assert_eq!(select.selected, 1);
+        select.handle_event(&Event::Click { x: 2, y: 0 }, area); // the top border
+        assert_eq!(select.selected, 1);
+    }
+
+    fn focus_test_screen(clicks: Rc<RefCell<u32>>) -> Screen {
+        let mut screen = Screen::new(20, 6);
+        screen.add(Rect::new(0, 0, 20, 1), Label::new("Pick a size:"));
+        screen.add(Rect::new(0, 1, 10, 4), SelectBox::new(&["Small", "Medium"]));
+        screen.add(
+            Rect::new(11, 1, 8, 3),
+            Button::new("OK").on_activate(move || *clicks.borrow_mut() += 1),
+        );
+        screen
+    }
+
+    #[test]
+    fn tab_cycles_focus_over_focusable_components() {
+        let mut screen = focus_test_screen(Rc::new(RefCell::new(0)));
+        assert_eq!(screen.focused(), None);
+
+        screen.dispatch(&Event::Key(Key::Tab));
+        assert_eq!(screen.focused(), Some(1));
+        screen.dispatch(&Event::Key(Key::Tab));
+        assert_eq!(screen.focused(), Some(2));
+        screen.dispatch(&Event::Key(Key::Tab));
+        assert_eq!(screen.focused(), Some(1));
+        screen.dispatch(&Event::Key(Key::BackTab));
+        assert_eq!(screen.focused(), Some(2));
+
+        assert_eq!(screen.render().get(11, 1).unwrap().style, Style::REVERSE);
+        assert_eq!(screen.render().get(0, 1).unwrap().style, Style::PLAIN);
+    }
+
+    #[test]
+    fn keys_go_to_the_focused_component() {
+        let clicks = Rc::new(RefCell::new(0));
+        let mut screen = focus_test_screen(Rc::clone(&clicks));
+
+        assert_eq!(
+            screen.dispatch(&Event::Key(Key::Enter)),
+            EventResult::Ignored
+        );
+        screen.focus_next();
+        screen.dispatch(&Event::Key(Key::Down));
+        assert!(screen.render().to_text().contains("│> Medium"));
+
+        screen.focus_next();
+        screen.dispatch(&Event::Key(Key::Enter));
+        screen.dispatch(&Event::Key(Key::Char(' ')));
+        assert_eq!(*clicks.borrow(), 2);
+    }
+
+    #[test]
+    fn clicks_activate_and_focus_the_component_under_the_mouse() {
+        let clicks = Rc::new(RefCell::new(0));
+        let mut screen = focus_test_screen(Rc::clone(&clicks));
+
+        assert_eq!(
+            screen.dispatch(&Event::Click { x: 14, y: 2 }),
+            EventResult::Handled
+        );
+        assert_eq!(*clicks.borrow(), 1);
+        assert_eq!(screen.focused(), Some(2));
+
+        screen.dispatch(&Event::Click { x: 3, y: 3 });
+        assert_eq!(screen.focused(), Some(1));
+        assert!(screen.render().to_text().contains("│> Medium"));
+
+        assert_eq!(
+            screen.dispatch(&Event::Click { x: 2, y: 0 }),
+            EventResult::Ignored
+        );
+        assert_eq!(*clicks.borrow(), 1);
+    }
+
+    #[test]
+    fn event_loop_runs_until_escape() {
+        let clicks = Rc::new(RefCell::new(0));
+        let mut screen = focus_test_screen(Rc::clone(&clicks));
+        let mut output = Vec::new();
+
+        screen
+            .run_with(&b"\t\r\x1b[<0;13;2M\x1bq"[..], &mut output)
+            .unwrap();
+        assert_eq!(*clicks.borrow(), 2);
+        assert_eq!(screen.focused(), Some(2));
+        assert!(!output.is_empty());
+    }
+
+    #[test]
+    fn components_are_clipped_to_their_area() {
+        let mut screen = Screen::new(6, 2);
+        screen.add(Rect::new(4, 0, 10, 1), Label::new("overflowing"));
+        screen.add(Rect::new(0, 1, 3, 1), Label::new("abcdef"));
+        assert_eq!(screen.render().to_text(), "    ov\nabc   \n");
+    }
+
+    /*
+        Compares the bytes a full redraw and a differential redraw send to the terminal
+        for each frame of a short session: every frame changes only a few cells, so the
+        differential redraw must send less than the full one each time, and less than a
+        third of it overall.
+    */
+    #[test]
+    fn differential_redraw_sends_fewer_bytes() {
+        let mut screen = focus_test_screen(Rc::new(RefCell::new(0)));
+        let events = [
+            Event::Key(Key::Tab),
+            Event::Key(Key::Down),
+            Event::Key(Key::Up),
+            Event::Key(Key::Tab),
+            Event::Click { x: 13, y: 2 },
+        ];
+        let mut frames = Vec::new();
+
+        for event in &events {
+            screen.flush_to(&mut Vec::new()).unwrap();
+            screen.dispatch(event);
+
+            let mut full = Vec::from("\x1b[2J");
+            screen.render().write_ansi(&mut full).unwrap();
+            let mut diff = Vec::new();
+            screen.flush_to(&mut diff).unwrap();
+            frames.push((event, full.len(), diff.len()));
+        }
+
+        // Every failure shows the bytes of each frame: full redraw, then differential.
+        let report: Vec<String> = frames
+            .iter()
+            .map(|(event, full, diff)| format!("{event:?}: full {full}, differential {diff}"))
+            .collect();
+        let report = report.join("\n");
+        for (event, full, diff) in &frames {
+            assert!(diff < full, "{event:?} is not cheaper to redraw:\n{report}");
+        }
+        let full_total: usize = frames.iter().map(|(_, full, _)| full).sum();
+        let diff_total: usize = frames.iter().map(|(_, _, diff)| diff).sum();
+        assert!(
+            diff_total * 3 < full_total,
+            "{diff_total} of {full_total} bytes:\n{report}"
+        );
+    }
+}
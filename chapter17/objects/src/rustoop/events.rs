@@ -0,0 +1,301 @@
+/*
+    Turns the raw bytes a terminal sends into input events.
+
+    In raw mode the terminal hands us every key press as soon as it happens, instead of
+    waiting for a whole line. Printable keys arrive as their UTF-8 bytes, while special
+    keys and mouse clicks arrive as escape sequences starting with ESC (0x1b):
+
+        ESC [ A, B, C, D      arrow keys up, down, right, left
+        ESC [ Z               shift + tab
+        ESC [ < b ; x ; y M   mouse button b pressed at column x, row y (counted from 1)
+        ESC c                 alt + c, for a printable character c
+*/
+
+use std::io;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Ctrl(char),
+    Alt(char),
+    Enter,
+    Tab,
+    BackTab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Key(Key),
+    Click { x: usize, y: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventResult {
+    Handled,
+    Ignored,
+}
+
+/*
+    A read may end in the middle of an escape sequence, so the decoder keeps any
+    incomplete bytes and prepends them to the next read.
+*/
+#[derive(Default)]
+pub struct InputDecoder {
+    pending: Vec<u8>,
+}
+
+impl InputDecoder {
+    pub fn new() -> InputDecoder {
+        InputDecoder {
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<Event> {
+        self.pending.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        let mut i = 0;
+
+        while i < self.pending.len() {
+            match decode_one(&self.pending[i..]) {
+                Decoded::Event(event, used) => {
+                    events.push(event);
+                    i += used;
+                }
+                Decoded::Skip(used) => i += used,
+                Decoded::Incomplete => break,
+            }
+        }
+
+        self.pending.drain(..i);
+        events
+    }
+}
+
+enum Decoded {
+    Event(Event, usize),
+    Skip(usize),
+    Incomplete,
+}
+
+fn decode_one(bytes: &[u8]) -> Decoded {
+    let key = |key, used| Decoded::Event(Event::Key(key), used);
+    match bytes[0] {
+        b'\r' | b'\n' => key(Key::Enter, 1),
+        b'\t' => key(Key::Tab, 1),
+        0x7f | 0x08 => key(Key::Backspace, 1),
+        0x1b => decode_escape(bytes),
+        0x00 => key(Key::Ctrl(' '), 1), // what terminals send for ctrl + space
+        byte @ 0x01..=0x1a => key(Key::Ctrl((b'a' + byte - 1) as char), 1),
+        _ => decode_char(bytes),
+    }
+}
+
+/*
+    A lone ESC at the end of the input is the Escape key itself; terminals write a
+    whole escape sequence at once, so the rest of a sequence never lags behind its ESC.
+    Holding alt sends ESC before the key: with a printable character that makes one
+    Alt event, and before any other key the ESC is dropped and the key decoded alone.
+*/
+fn decode_escape(bytes: &[u8]) -> Decoded {
+    let key = |key, used| Decoded::Event(Event::Key(key), used);
+    match bytes.get(1) {
+        None => key(Key::Escape, 1),
+        Some(b'[') => match bytes.get(2) {
+            None => Decoded::Incomplete,
+            Some(b'A') => key(Key::Up, 3),
+            Some(b'B') => key(Key::Down, 3),
+            Some(b'C') => key(Key::Right, 3),
+            Some(b'D') => key(Key::Left, 3),
+            Some(b'Z') => key(Key::BackTab, 3),
+            Some(b'<') => decode_mouse(bytes),
+            Some(_) => skip_sequence(bytes),
+        },
+        Some(&byte) if byte < 0x20 || byte == 0x7f => Decoded::Skip(1),
+        Some(_) => match decode_char(&bytes[1..]) {
+            Decoded::Event(Event::Key(Key::Char(c)), used) => key(Key::Alt(c), used + 1),
+            Decoded::Incomplete => Decoded::Incomplete,
+            _ => Decoded::Skip(1),
+        },
+    }
+}
+
+fn decode_mouse(bytes: &[u8]) -> Decoded {
+    let end = match bytes.iter().position(|b| *b == b'M' || *b == b'm') {
+        Some(end) => end,
+        None => return Decoded::Incomplete,
+    };
+    let params = String::from_utf8_lossy(&bytes[3..end]);
+    let numbers: Vec<usize> = params.split(';').filter_map(|n| n.parse().ok()).collect();
+
+    match (numbers.as_slice(), bytes[end]) {
+        ([0, x, y], b'M') if *x > 0 && *y > 0 => {
+            Decoded::Event(Event::Click { x: x - 1, y: y - 1 }, end + 1)
+        }
+        _ => Decoded::Skip(end + 1), // releases, other buttons and movement
+    }
+}
+
+/*
+    Skips escape sequences we don't understand: ESC [ then parameter bytes, ending
+    with a final byte in the range @ to ~.
+*/
+fn skip_sequence(bytes: &[u8]) -> Decoded {
+    match bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b)) {
+        Some(end) => Decoded::Skip(end + 3),
+        None => Decoded::Incomplete,
+    }
+}
+
+fn decode_char(bytes: &[u8]) -> Decoded {
+    let len = match bytes[0] {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Decoded::Skip(1),
+    };
+    if bytes.len() < len {
+        return Decoded::Incomplete;
+    }
+    match std::str::from_utf8(&bytes[..len])
+        .ok()
+        .and_then(|s| s.chars().next())
+    {
+        Some(c) => Decoded::Event(Event::Key(Key::Char(c)), len),
+        None => Decoded::Skip(1),
+    }
+}
+
+/*
+    Puts the terminal in raw mode until dropped. The standard library has no terminal
+    API, so we ask stty to do it, saving the previous settings to restore afterwards.
+    We also turn on mouse click reporting (modes 1000 and 1006) and hide the cursor.
+*/
+pub struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        print!("\x1b[?1000h\x1b[?1006h\x1b[?25l");
+        Ok(RawMode {
+            saved: saved.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        print!("\x1b[?1000l\x1b[?1006l\x1b[?25h\x1b[0m\r\n");
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+/*
+    Asks the terminal for its size; stty prints the number of rows, then columns.
+*/
+pub fn terminal_size() -> io::Result<(usize, usize)> {
+    let size = stty(&["size"])?;
+    let mut numbers = size.split_whitespace().map(|n| n.parse::<usize>());
+    match (numbers.next(), numbers.next()) {
+        (Some(Ok(rows)), Some(Ok(columns))) => Ok((columns, rows)),
+        _ => Err(io::Error::other("unexpected output from stty size")),
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_keys() {
+        let mut decoder = InputDecoder::new();
+        assert_eq!(
+            decoder.decode("a\t\x1b[Z\r\x1b[A\x1b[B\x7f\x03é".as_bytes()),
+            [
+                Event::Key(Key::Char('a')),
+                Event::Key(Key::Tab),
+                Event::Key(Key::BackTab),
+                Event::Key(Key::Enter),
+                Event::Key(Key::Up),
+                Event::Key(Key::Down),
+                Event::Key(Key::Backspace),
+                Event::Key(Key::Ctrl('c')),
+                Event::Key(Key::Char('é')),
+            ]
+        );
+    }
+
+    #[test]
+    fn nul_is_ctrl_space() {
+        let mut decoder = InputDecoder::new();
+        assert_eq!(
+            decoder.decode(b"\x00a"),
+            [Event::Key(Key::Ctrl(' ')), Event::Key(Key::Char('a'))]
+        );
+    }
+
+    #[test]
+    fn decodes_mouse_clicks_and_skips_releases() {
+        let mut decoder = InputDecoder::new();
+        assert_eq!(
+            decoder.decode(b"\x1b[<0;12;3M\x1b[<0;12;3m\x1b[<2;1;1M"),
+            [Event::Click { x: 11, y: 2 }]
+        );
+    }
+
+    #[test]
+    fn keeps_incomplete_sequences_for_the_next_read() {
+        let mut decoder = InputDecoder::new();
+        assert_eq!(decoder.decode(b"x\x1b[<0;5"), [Event::Key(Key::Char('x'))]);
+        assert_eq!(decoder.decode(b";6M"), [Event::Click { x: 4, y: 5 }]);
+
+        assert_eq!(decoder.decode(&[0xc3]), []);
+        assert_eq!(decoder.decode(&[0xa9]), [Event::Key(Key::Char('é'))]);
+    }
+
+    #[test]
+    fn lone_escape_is_the_escape_key() {
+        let mut decoder = InputDecoder::new();
+        assert_eq!(decoder.decode(b"\x1b"), [Event::Key(Key::Escape)]);
+        assert_eq!(decoder.decode(b"\x1b[15~q"), [Event::Key(Key::Char('q'))]);
+    }
+
+    #[test]
+    fn escape_before_a_key_is_alt() {
+        let mut decoder = InputDecoder::new();
+        assert_eq!(
+            decoder.decode("\x1bq\x1bé\x1b\r\x1b\x1b[A".as_bytes()),
+            [
+                Event::Key(Key::Alt('q')),
+                Event::Key(Key::Alt('é')),
+                Event::Key(Key::Enter),
+                Event::Key(Key::Up),
+            ]
+        );
+
+        assert_eq!(decoder.decode(&[0x1b, 0xc3]), []);
+        assert_eq!(decoder.decode(&[0xa9]), [Event::Key(Key::Alt('é'))]);
+    }
+}
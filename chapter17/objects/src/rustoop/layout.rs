@@ -0,0 +1,468 @@
+/*
+    Containers that lay out other components, so a screen can be described as a tree
+    instead of a list of hand computed positions. A container is itself a Component,
+    which means containers nest: a vertical Stack can hold a Grid that holds buttons.
+
+    Containers don't store the areas of their children. Each time they are drawn or
+    receive an event they split the area they were given according to the constraints
+    of their children, so resizing the Screen lays everything out again.
+
+        Fixed(n)         exactly n cells
+        Percentage(p)    p percent of the container, rounded down
+        Fill             an equal share of whatever is left
+*/
+
+use super::canvas::{Canvas, Rect};
+use super::events::{Event, EventResult};
+use super::{Component, Draw};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+    Fixed(usize),
+    Percentage(u16),
+    Fill,
+}
+
+/*
+    Splits a length into one size per constraint. Fixed and percentage sizes are given
+    out first; when they don't fit, the last ones are shrunk until they do. The rest is
+    shared between the Fill constraints, the first ones getting a cell more when it
+    doesn't divide evenly, so the sizes always add up to the whole length if there is
+    at least one Fill.
+*/
+pub fn split(total: usize, constraints: &[Constraint]) -> Vec<usize> {
+    let mut sizes: Vec<usize> = constraints
+        .iter()
+        .map(|constraint| match constraint {
+            Constraint::Fixed(size) => *size,
+            Constraint::Percentage(percent) => total * usize::from(*percent).min(100) / 100,
+            Constraint::Fill => 0,
+        })
+        .collect();
+
+    let mut excess = sizes.iter().sum::<usize>().saturating_sub(total);
+    for size in sizes.iter_mut().rev() {
+        let cut = excess.min(*size);
+        *size -= cut;
+        excess -= cut;
+    }
+
+    let remaining = total - sizes.iter().sum::<usize>();
+    let fills = constraints
+        .iter()
+        .filter(|c| **c == Constraint::Fill)
+        .count();
+    if let Some(share) = remaining.checked_div(fills) {
+        let mut extra = remaining % fills;
+        for (size, constraint) in sizes.iter_mut().zip(constraints) {
+            if *constraint == Constraint::Fill {
+                *size = share + usize::from(extra > 0);
+                extra = extra.saturating_sub(1);
+            }
+        }
+    }
+    sizes
+}
+
+/*
+    Returns the start offset of each size, beginning at start.
+*/
+fn offsets(start: usize, sizes: &[usize]) -> Vec<usize> {
+    sizes
+        .iter()
+        .scan(start, |offset, size| {
+            let current = *offset;
+            *offset += size;
+            Some(current)
+        })
+        .collect()
+}
+
+/*
+    The children of a container, and which of them has the focus. Stack and Grid only
+    differ in how they compute the areas; drawing, focus and events work the same way.
+*/
+struct Children {
+    components: Vec<Box<dyn Component>>,
+    focus: Option<usize>,
+}
+
+impl Children {
+    fn new() -> Children {
+        Children {
+            components: Vec::new(),
+            focus: None,
+        }
+    }
+
+    fn draw(&self, canvas: &mut Canvas, areas: &[Rect]) {
+        for (component, area) in self.components.iter().zip(areas) {
+            component.draw(canvas, *area);
+        }
+    }
+
+    fn focusable(&self) -> bool {
+        self.components
+            .iter()
+            .any(|component| component.focusable())
+    }
+
+    fn unfocus(&mut self) {
+        if let Some(old) = self.focus.take() {
+            self.components[old].set_focused(false);
+        }
+    }
+
+    fn focus_child(&mut self, index: usize, forwards: bool) {
+        self.unfocus();
+        self.components[index].set_focused(true);
+        self.components[index].focus_step(forwards);
+        self.focus = Some(index);
+    }
+
+    /*
+        Gives the focused child the chance to move within itself first, then moves to
+        the next focusable child. Unlike the Screen it doesn't wrap around: running off
+        the end hands the focus back to the parent.
+    */
+    fn focus_step(&mut self, forwards: bool) -> bool {
+        let count = self.components.len();
+        let mut candidates: Box<dyn Iterator<Item = usize>> = match (self.focus, forwards) {
+            (Some(i), _) if self.components[i].focus_step(forwards) => return true,
+            (Some(i), true) => Box::new(i + 1..count),
+            (Some(i), false) => Box::new((0..i).rev()),
+            (None, true) => Box::new(0..count),
+            (None, false) => Box::new((0..count).rev()),
+        };
+        match candidates.find(|&i| self.components[i].focusable()) {
+            Some(next) => {
+                self.focus_child(next, forwards);
+                true
+            }
+            None => {
+                self.unfocus();
+                false
+            }
+        }
+    }
+
+    /*
+        Keys go to the focused child. A click goes to the topmost child under the mouse,
+        which takes the focus if it can.
+    */
+    fn handle_event(&mut self, event: &Event, areas: &[Rect]) -> EventResult {
+        match event {
+            Event::Key(_) => match self.focus {
+                Some(i) => self.components[i].handle_event(event, areas[i]),
+                None => EventResult::Ignored,
+            },
+            Event::Click { x, y } => {
+                for i in (0..self.components.len()).rev() {
+                    if !areas[i].contains(*x, *y) {
+                        continue;
+                    }
+                    if self.components[i].handle_event(event, areas[i]) == EventResult::Handled {
+                        if self.components[i].focusable() && self.focus != Some(i) {
+                            self.unfocus();
+                            self.components[i].set_focused(true);
+                            self.focus = Some(i);
+                        }
+                        return EventResult::Handled;
+                    }
+                }
+                EventResult::Ignored
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Vertical,
+    Horizontal,
+}
+
+/*
+    Lays its children out one after another, top to bottom or left to right, each
+    taking the whole width (or height) of the stack.
+*/
+pub struct Stack {
+    pub direction: Direction,
+    constraints: Vec<Constraint>,
+    children: Children,
+}
+
+impl Stack {
+    pub fn new(direction: Direction) -> Stack {
+        Stack {
+            direction,
+            constraints: Vec::new(),
+            children: Children::new(),
+        }
+    }
+
+    pub fn vertical() -> Stack {
+        Stack::new(Direction::Vertical)
+    }
+
+    pub fn horizontal() -> Stack {
+        Stack::new(Direction::Horizontal)
+    }
+
+    /*
+        Adds a child after the existing ones. Taking and returning self lets a whole
+        tree be written as one expression.
+    */
+    pub fn with<C: Component + 'static>(mut self, constraint: Constraint, component: C) -> Stack {
+        self.constraints.push(constraint);
+        self.children.components.push(Box::new(component));
+        self
+    }
+
+    pub fn areas(&self, area: Rect) -> Vec<Rect> {
+        match self.direction {
+            Direction::Vertical => {
+                let heights = split(area.height, &self.constraints);
+                offsets(area.y, &heights)
+                    .into_iter()
+                    .zip(heights)
+                    .map(|(y, height)| Rect::new(area.x, y, area.width, height))
+                    .collect()
+            }
+            Direction::Horizontal => {
+                let widths = split(area.width, &self.constraints);
+                offsets(area.x, &widths)
+                    .into_iter()
+                    .zip(widths)
+                    .map(|(x, width)| Rect::new(x, area.y, width, area.height))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Draw for Stack {
+    fn draw(&self, canvas: &mut Canvas, area: Rect) {
+        self.children.draw(canvas, &self.areas(area));
+    }
+}
+
+impl Component for Stack {
+    fn focusable(&self) -> bool {
+        self.children.focusable()
+    }
+    fn set_focused(&mut self, focused: bool) {
+        if !focused {
+            self.children.unfocus();
+        }
+    }
+    fn focus_step(&mut self, forwards: bool) -> bool {
+        self.children.focus_step(forwards)
+    }
+    fn handle_event(&mut self, event: &Event, area: Rect) -> EventResult {
+        let areas = self.areas(area);
+        self.children.handle_event(event, &areas)
+    }
+}
+
+/*
+    Splits its area into rows and columns and puts each child in one cell. Tab moves
+    through the children in the order they were added, not the order of the cells.
+*/
+pub struct Grid {
+    rows: Vec<Constraint>,
+    columns: Vec<Constraint>,
+    cells: Vec<(usize, usize)>,
+    children: Children,
+}
+
+impl Grid {
+    pub fn new(rows: &[Constraint], columns: &[Constraint]) -> Grid {
+        Grid {
+            rows: rows.to_vec(),
+            columns: columns.to_vec(),
+            cells: Vec::new(),
+            children: Children::new(),
+        }
+    }
+
+    pub fn with<C: Component + 'static>(mut self, row: usize, column: usize, component: C) -> Grid {
+        if row >= self.rows.len() || column >= self.columns.len() {
+            panic!(
+                "Cell ({row}, {column}) is outside a grid of {} rows and {} columns.",
+                self.rows.len(),
+                self.columns.len()
+            );
+        }
+        self.cells.push((row, column));
+        self.children.components.push(Box::new(component));
+        self
+    }
+
+    pub fn areas(&self, area: Rect) -> Vec<Rect> {
+        let heights = split(area.height, &self.rows);
+        let widths = split(area.width, &self.columns);
+        let ys = offsets(area.y, &heights);
+        let xs = offsets(area.x, &widths);
+        self.cells
+            .iter()
+            .map(|&(row, column)| Rect::new(xs[column], ys[row], widths[column], heights[row]))
+            .collect()
+    }
+}
+
+impl Draw for Grid {
+    fn draw(&self, canvas: &mut Canvas, area: Rect) {
+        self.children.draw(canvas, &self.areas(area));
+    }
+}
+
+impl Component for Grid {
+    fn focusable(&self) -> bool {
+        self.children.focusable()
+    }
+    fn set_focused(&mut self, focused: bool) {
+        if !focused {
+            self.children.unfocus();
+        }
+    }
+    fn focus_step(&mut self, forwards: bool) -> bool {
+        self.children.focus_step(forwards)
+    }
+    fn handle_event(&mut self, event: &Event, area: Rect) -> EventResult {
+        let areas = self.areas(area);
+        self.children.handle_event(event, &areas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::components::{Button, Label, SelectBox};
+    use super::super::events::Key;
+    use super::super::Screen;
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn split_gives_fixed_and_percentage_sizes_first() {
+        use Constraint::*;
+        assert_eq!(split(20, &[Fixed(3), Percentage(50), Fill]), [3, 10, 7]);
+        assert_eq!(split(10, &[Fill, Fixed(2), Fill, Fill]), [3, 2, 3, 2]);
+        assert_eq!(split(10, &[Fixed(4), Percentage(30)]), [4, 3]);
+    }
+
+    #[test]
+    fn split_shrinks_the_last_constraints_when_space_runs_out() {
+        use Constraint::*;
+        assert_eq!(split(5, &[Fixed(3), Fixed(4), Fill]), [3, 2, 0]);
+        assert_eq!(split(0, &[Fixed(3), Percentage(150), Fill]), [0, 0, 0]);
+        assert_eq!(split(10, &[Percentage(150)]), [10]);
+    }
+
+    #[test]
+    fn stacks_nest_and_follow_the_screen_size() {
+        let form = Stack::vertical()
+            .with(Constraint::Fixed(1), Label::new("Title"))
+            .with(
+                Constraint::Fill,
+                Stack::horizontal()
+                    .with(Constraint::Percentage(50), Button::new("A"))
+                    .with(Constraint::Fill, Button::new("B")),
+            );
+        let mut screen = Screen::new(12, 4);
+        screen.add_full(form);
+
+        assert_eq!(
+            screen.render().to_text(),
+            "Title       \n\
+             ┌────┐┌────┐\n\
+             │ A  ││ B  │\n\
+             └────┘└────┘\n"
+        );
+
+        screen.resize(8, 5);
+        assert_eq!(
+            screen.render().to_text(),
+            "Title   \n\
+             ┌──┐┌──┐\n\
+             │A ││B │\n\
+             │  ││  │\n\
+             └──┘└──┘\n"
+        );
+    }
+
+    #[test]
+    fn grid_places_children_in_cells() {
+        let grid = Grid::new(
+            &[Constraint::Fixed(1), Constraint::Fill],
+            &[Constraint::Fixed(4), Constraint::Fill],
+        )
+        .with(0, 0, Label::new("Name"))
+        .with(1, 1, Label::new("value"));
+
+        assert_eq!(
+            grid.areas(Rect::new(1, 1, 10, 3)),
+            [Rect::new(1, 1, 4, 1), Rect::new(5, 2, 6, 2)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "outside a grid")]
+    fn grid_rejects_cells_outside_it() {
+        Grid::new(&[Constraint::Fill], &[Constraint::Fill]).with(1, 0, Label::new("x"));
+    }
+
+    fn counting_button(label: &str, presses: &Rc<RefCell<Vec<String>>>) -> Button {
+        let presses = Rc::clone(presses);
+        let name = String::from(label);
+        Button::new(label).on_activate(move || presses.borrow_mut().push(name.clone()))
+    }
+
+    fn nested_screen(presses: &Rc<RefCell<Vec<String>>>) -> Screen {
+        let buttons = Grid::new(&[Constraint::Fill], &[Constraint::Fill, Constraint::Fill])
+            .with(0, 0, counting_button("A", presses))
+            .with(0, 1, counting_button("B", presses));
+        let mut screen = Screen::new(20, 8);
+        screen.add_full(
+            Stack::vertical()
+                .with(Constraint::Fixed(4), SelectBox::new(&["x", "y"]))
+                .with(Constraint::Fill, buttons),
+        );
+        screen.add(Rect::new(0, 7, 6, 1), counting_button("C", presses));
+        screen
+    }
+
+    #[test]
+    fn tab_walks_through_nested_containers() {
+        let presses = Rc::new(RefCell::new(Vec::new()));
+        let mut screen = nested_screen(&presses);
+
+        for _ in 0..5 {
+            screen.dispatch(&Event::Key(Key::Tab));
+            screen.dispatch(&Event::Key(Key::Enter));
+        }
+        assert_eq!(*presses.borrow(), ["A", "B", "C"]);
+
+        screen.dispatch(&Event::Key(Key::BackTab)); // from the select box back to C
+        screen.dispatch(&Event::Key(Key::BackTab));
+        screen.dispatch(&Event::Key(Key::Enter));
+        assert_eq!(presses.borrow().last().unwrap(), "B");
+    }
+
+    #[test]
+    fn clicks_reach_nested_children_and_move_the_focus() {
+        let presses = Rc::new(RefCell::new(Vec::new()));
+        let mut screen = nested_screen(&presses);
+
+        assert_eq!(
+            screen.dispatch(&Event::Click { x: 15, y: 5 }),
+            EventResult::Handled
+        );
+        assert_eq!(screen.focused(), Some(0));
+        screen.dispatch(&Event::Key(Key::Enter));
+        screen.dispatch(&Event::Key(Key::BackTab));
+        screen.dispatch(&Event::Key(Key::Enter));
+        assert_eq!(*presses.borrow(), ["B", "B", "A"]);
+    }
+}
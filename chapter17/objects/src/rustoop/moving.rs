@@ -0,0 +1,343 @@
+/*
+    Moving averages, for metrics where only recent samples matter. They share the
+    interface of AveragedCollection through the Averaging trait, so a dashboard can hold
+    a Vec<Box<dyn Averaging<f64>>> and feed every kind of average the same samples.
+
+    - WindowedCollection averages the last N samples, or the samples of the last T
+      seconds, dropping older ones as new ones arrive.
+    - ExponentialAverage weighs every sample, but each new one by a factor alpha and
+      all the older ones together by 1 - alpha, so old samples fade out gradually.
+*/
+
+use super::stats::{Accumulator, Sample};
+use super::AveragedCollection;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/*
+    remove always takes back the most recently added sample that is still held.
+*/
+pub trait Averaging<T: Sample> {
+    fn add(&mut self, value: T);
+    fn remove(&mut self) -> Option<T>;
+    fn average(&self) -> Option<f64>;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Sample> Averaging<T> for AveragedCollection<T> {
+    fn add(&mut self, value: T) {
+        AveragedCollection::add(self, value);
+    }
+    fn remove(&mut self) -> Option<T> {
+        AveragedCollection::remove(self)
+    }
+    fn average(&self) -> Option<f64> {
+        AveragedCollection::average(self)
+    }
+    fn len(&self) -> usize {
+        AveragedCollection::len(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    Last(usize),
+    Within(Duration),
+}
+
+pub struct WindowedCollection<T: Sample> {
+    window: Window,
+    samples: VecDeque<(T, Instant)>,
+    totals: T::Accumulator,
+}
+
+/*
+    The accumulators can take out any value they were given, not only the last one, so
+    samples can leave from the old end of the window as well as the new one, and the
+    average is still updated without going through the window again.
+*/
+impl<T: Sample> WindowedCollection<T> {
+    pub fn new(window: Window) -> WindowedCollection<T> {
+        WindowedCollection {
+            window,
+            samples: VecDeque::new(),
+            totals: T::Accumulator::default(),
+        }
+    }
+
+    pub fn last(count: usize) -> WindowedCollection<T> {
+        WindowedCollection::new(Window::Last(count))
+    }
+
+    pub fn within(duration: Duration) -> WindowedCollection<T> {
+        WindowedCollection::new(Window::Within(duration))
+    }
+
+    pub fn window(&self) -> Window {
+        self.window
+    }
+
+    pub fn add(&mut self, value: T) {
+        self.add_at(value, Instant::now());
+    }
+
+    /*
+        Adds a sample taken at the given time. Samples are expected in time order.
+    */
+    pub fn add_at(&mut self, value: T, at: Instant) {
+        self.samples.push_back((value, at));
+        self.totals.add(value);
+        self.expire_at(at);
+    }
+
+    pub fn remove(&mut self) -> Option<T> {
+        let (value, _) = self.samples.pop_back()?;
+        self.totals.remove(value);
+        Some(value)
+    }
+
+    /*
+        With a time window, samples only leave when something happens. When no new
+        samples arrive for a while, call expire before reading, so the old ones go.
+    */
+    pub fn expire(&mut self) {
+        self.expire_at(Instant::now());
+    }
+
+    pub fn expire_at(&mut self, now: Instant) {
+        while let Some(&(value, at)) = self.samples.front() {
+            let expired = match self.window {
+                Window::Last(count) => self.samples.len() > count,
+                Window::Within(duration) => now.saturating_duration_since(at) >= duration,
+            };
+            if !expired {
+                break;
+            }
+            self.samples.pop_front();
+            self.totals.remove(value);
+        }
+    }
+
+    pub fn average(&self) -> Option<f64> {
+        self.totals.mean()
+    }
+
+    pub fn variance(&self) -> Option<f64> {
+        self.totals.variance()
+    }
+
+    pub fn standard_deviation(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+impl<T: Sample> Averaging<T> for WindowedCollection<T> {
+    fn add(&mut self, value: T) {
+        WindowedCollection::add(self, value);
+    }
+    fn remove(&mut self) -> Option<T> {
+        WindowedCollection::remove(self)
+    }
+    fn average(&self) -> Option<f64> {
+        WindowedCollection::average(self)
+    }
+    fn len(&self) -> usize {
+        WindowedCollection::len(self)
+    }
+}
+
+/*
+    Each sample moves the average a fraction alpha of the way towards itself:
+
+        average = average + alpha * (sample - average)
+
+    A larger alpha follows changes faster, a smaller one smooths out more noise. To
+    be able to take samples back with remove, the average before each sample is kept
+    along with it, so the history grows with the samples like AveragedCollection's list.
+*/
+pub struct ExponentialAverage<T: Sample> {
+    alpha: f64,
+    average: Option<f64>,
+    history: Vec<(T, Option<f64>)>,
+}
+
+impl<T: Sample> ExponentialAverage<T> {
+    pub fn new(alpha: f64) -> ExponentialAverage<T> {
+        if !(alpha > 0.0 && alpha <= 1.0) {
+            panic!("The smoothing factor must be greater than 0 and at most 1, got {alpha}.");
+        }
+        ExponentialAverage {
+            alpha,
+            average: None,
+            history: Vec::new(),
+        }
+    }
+
+    /*
+        The usual way to pick alpha: 2 / (span + 1) gives the samples roughly the same
+        weight as a plain average over the last span samples would.
+    */
+    pub fn with_span(span: usize) -> ExponentialAverage<T> {
+        ExponentialAverage::new(2.0 / (span as f64 + 1.0))
+    }
+
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    pub fn add(&mut self, value: T) {
+        self.history.push((value, self.average));
+        let sample = value.to_f64();
+        self.average = Some(match self.average {
+            Some(average) => average + self.alpha * (sample - average),
+            None => sample,
+        });
+    }
+
+    pub fn remove(&mut self) -> Option<T> {
+        let (value, previous) = self.history.pop()?;
+        self.average = previous;
+        Some(value)
+    }
+
+    pub fn average(&self) -> Option<f64> {
+        self.average
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+}
+
+impl<T: Sample> Averaging<T> for ExponentialAverage<T> {
+    fn add(&mut self, value: T) {
+        ExponentialAverage::add(self, value);
+    }
+    fn remove(&mut self) -> Option<T> {
+        ExponentialAverage::remove(self)
+    }
+    fn average(&self) -> Option<f64> {
+        ExponentialAverage::average(self)
+    }
+    fn len(&self) -> usize {
+        ExponentialAverage::len(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_window_keeps_the_last_samples() {
+        let mut window = WindowedCollection::last(3);
+        assert_eq!(window.average(), None);
+        for value in [10, 20, 30, 40] {
+            window.add(value);
+        }
+        assert_eq!(window.len(), 3);
+        assert_eq!(window.average(), Some(30.0));
+
+        assert_eq!(window.remove(), Some(40));
+        assert_eq!(window.average(), Some(25.0));
+        window.add(-50);
+        assert_eq!(window.average(), Some(0.0));
+        assert!((window.variance().unwrap() - 3800.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn empty_count_window_holds_nothing() {
+        let mut window = WindowedCollection::last(0);
+        window.add(1.5);
+        assert!(window.is_empty());
+        assert_eq!(window.average(), None);
+    }
+
+    #[test]
+    fn time_window_drops_old_samples() {
+        let start = Instant::now();
+        let seconds = |s| start + Duration::from_secs(s);
+        let mut window = WindowedCollection::within(Duration::from_secs(10));
+
+        window.add_at(1.0, seconds(0));
+        window.add_at(2.0, seconds(5));
+        window.add_at(6.0, seconds(9));
+        assert_eq!(window.average(), Some(3.0));
+
+        window.add_at(4.0, seconds(12)); // the sample from second 0 is now too old
+        assert_eq!(window.average(), Some(4.0));
+
+        window.expire_at(seconds(19));
+        assert_eq!(window.len(), 1);
+        assert_eq!(window.average(), Some(4.0));
+        window.expire_at(seconds(30));
+        assert_eq!(window.average(), None);
+    }
+
+    #[test]
+    fn exponential_average_follows_new_samples() {
+        let mut average = ExponentialAverage::new(0.5);
+        average.add(10);
+        assert_eq!(average.average(), Some(10.0));
+        average.add(20);
+        average.add(20);
+        assert_eq!(average.average(), Some(17.5));
+
+        assert_eq!(average.remove(), Some(20));
+        assert_eq!(average.average(), Some(15.0));
+        average.remove();
+        average.remove();
+        assert_eq!(average.remove(), None);
+        assert_eq!(average.average(), None);
+    }
+
+    #[test]
+    fn span_gives_the_usual_alpha() {
+        assert_eq!(ExponentialAverage::<f64>::with_span(3).alpha(), 0.5);
+        assert_eq!(ExponentialAverage::<f64>::with_span(1).alpha(), 1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "smoothing factor")]
+    fn alpha_must_be_a_fraction() {
+        ExponentialAverage::<f64>::new(0.0);
+    }
+
+    #[test]
+    fn every_kind_of_average_shares_one_interface() {
+        let mut averages: Vec<Box<dyn Averaging<u32>>> = vec![
+            Box::new(AveragedCollection::new()),
+            Box::new(WindowedCollection::last(2)),
+            Box::new(ExponentialAverage::new(0.5)),
+        ];
+        for average in averages.iter_mut() {
+            for value in [3, 6, 12] {
+                average.add(value);
+            }
+        }
+        let results: Vec<Option<f64>> = averages.iter().map(|a| a.average()).collect();
+        assert_eq!(results, [Some(7.0), Some(9.0), Some(8.25)]);
+
+        for average in averages.iter_mut() {
+            assert_eq!(average.remove(), Some(12));
+        }
+        let results: Vec<Option<f64>> = averages.iter().map(|a| a.average()).collect();
+        assert_eq!(results, [Some(4.5), Some(6.0), Some(4.5)]);
+    }
+}
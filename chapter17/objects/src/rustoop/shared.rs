@@ -0,0 +1,320 @@
+/*
+    An AveragedCollection that many threads can add samples to at once. Like the counter
+    in chapter 16, it is shared through an Arc and its data is protected by a Mutex:
+
+        let samples = Arc::new(SharedAveragedCollection::new());
+        let handle = {
+            let samples = Arc::clone(&samples);
+            thread::spawn(move || samples.add(42))
+        };
+
+    Behind a single Mutex every add would wait for the previous one to finish. Instead
+    the totals are split into shards, each with its own Mutex, and each thread adds to
+    its own shard, so threads rarely wait for each other. Nothing needs to know about
+    every sample at once until somebody asks for the statistics; reading merges the
+    shards into one summary.
+
+    Samples can't be removed: with many threads adding, "the last sample" isn't
+    well defined. The median isn't offered either, since it can't be merged from
+    per-shard summaries.
+*/
+
+use super::stats::{Accumulator, Sample};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/*
+    Every thread gets its own number the first time it adds a sample, and uses the
+    shard at that number modulo the shard count from then on. Consecutive numbers
+    spread the threads evenly over the shards.
+*/
+static NEXT_THREAD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_NUMBER: usize = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+}
+
+/*
+    The statistics of some samples: those of one shard, or of all of them merged.
+*/
+#[derive(Debug, Clone)]
+pub struct Summary<T: Sample> {
+    totals: T::Accumulator,
+    count: usize,
+    min: Option<T>,
+    max: Option<T>,
+}
+
+impl<T: Sample> Summary<T> {
+    fn new() -> Summary<T> {
+        Summary {
+            totals: T::Accumulator::default(),
+            count: 0,
+            min: None,
+            max: None,
+        }
+    }
+
+    fn add(&mut self, value: T) {
+        self.totals.add(value);
+        self.count += 1;
+        self.add_extreme(value);
+    }
+
+    fn merge(&mut self, other: &Summary<T>) {
+        self.totals.merge(&other.totals);
+        self.count += other.count;
+        if let Some(min) = other.min {
+            self.add_extreme(min);
+        }
+        if let Some(max) = other.max {
+            self.add_extreme(max);
+        }
+    }
+
+    /*
+        Updates the minimum and maximum as if value had been added.
+    */
+    fn add_extreme(&mut self, value: T) {
+        if self.min.is_none_or(|min| value.key() < min.key()) {
+            self.min = Some(value);
+        }
+        if self.max.is_none_or(|max| value.key() > max.key()) {
+            self.max = Some(value);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn average(&self) -> Option<f64> {
+        self.totals.mean()
+    }
+
+    pub fn variance(&self) -> Option<f64> {
+        self.totals.variance()
+    }
+
+    pub fn standard_deviation(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+
+    pub fn min(&self) -> Option<T> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<T> {
+        self.max
+    }
+}
+
+pub struct SharedAveragedCollection<T: Sample> {
+    shards: Vec<Mutex<Summary<T>>>,
+}
+
+impl<T: Sample> SharedAveragedCollection<T> {
+    /*
+        One shard per thread the machine can run at the same time.
+    */
+    pub fn new() -> SharedAveragedCollection<T> {
+        let shards = thread::available_parallelism().map_or(4, |n| n.get());
+        SharedAveragedCollection::with_shards(shards)
+    }
+
+    pub fn with_shards(shards: usize) -> SharedAveragedCollection<T> {
+        if shards == 0 {
+            panic!("A SharedAveragedCollection needs at least one shard.");
+        }
+        SharedAveragedCollection {
+            shards: (0..shards).map(|_| Mutex::new(Summary::new())).collect(),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /*
+        Adds to the thread's own shard. If another thread holds that one right now,
+        any other free shard will do; only when all of them are busy do we wait.
+    */
+    pub fn add(&self, value: T) {
+        let count = self.shards.len();
+        let own = THREAD_NUMBER.with(|number| *number) % count;
+        for offset in 0..count {
+            if let Ok(mut shard) = self.shards[(own + offset) % count].try_lock() {
+                shard.add(value);
+                return;
+            }
+        }
+        self.shards[own].lock().unwrap().add(value);
+    }
+
+    /*
+        Merges the shards one at a time, so adds can carry on in the other shards
+        meanwhile. The summary includes every sample whose add finished before
+        summary was called, and possibly some added while it ran.
+    */
+    pub fn summary(&self) -> Summary<T> {
+        let mut summary = Summary::new();
+        for shard in self.shards.iter() {
+            summary.merge(&shard.lock().unwrap());
+        }
+        summary
+    }
+
+    pub fn len(&self) -> usize {
+        self.summary().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn average(&self) -> Option<f64> {
+        self.summary().average()
+    }
+}
+
+impl<T: Sample> Default for SharedAveragedCollection<T> {
+    fn default() -> SharedAveragedCollection<T> {
+        SharedAveragedCollection::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::AveragedCollection;
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    const THREADS: usize = 8;
+    const SAMPLES_PER_THREAD: u32 = 10_000;
+
+    #[test]
+    fn empty_collection_has_no_statistics() {
+        let samples: SharedAveragedCollection<f64> = SharedAveragedCollection::with_shards(3);
+        let summary = samples.summary();
+        assert!(summary.is_empty());
+        assert_eq!(summary.average(), None);
+        assert_eq!(summary.min(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one shard")]
+    fn needs_a_shard() {
+        SharedAveragedCollection::<u32>::with_shards(0);
+    }
+
+    #[test]
+    fn merged_summary_matches_a_single_collection() {
+        let samples = SharedAveragedCollection::with_shards(4);
+        let mut single = AveragedCollection::new();
+        for value in [7, -3, 12, 0, 5, 5] {
+            samples.add(value);
+            single.add(value);
+        }
+        let summary = samples.summary();
+        assert_eq!(summary.len(), single.len());
+        assert_eq!(summary.average(), single.average());
+        assert_eq!(summary.variance(), single.variance());
+        assert_eq!((summary.min(), summary.max()), (single.min(), single.max()));
+    }
+
+    /*
+        The same pattern as using_arc in chapter 16's sharedstate.rs: every thread gets
+        its own clone of the Arc and adds to the shared collection.
+    */
+    #[test]
+    fn concurrent_adds_are_all_counted() {
+        let samples = Arc::new(SharedAveragedCollection::with_shards(4));
+        let mut handles = vec![];
+
+        for thread in 0..THREADS as u32 {
+            let samples = Arc::clone(&samples);
+            let handle = thread::spawn(move || {
+                for i in 0..SAMPLES_PER_THREAD {
+                    samples.add(thread * SAMPLES_PER_THREAD + i);
+                }
+            });
+            handles.push(handle);
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let total = THREADS as u32 * SAMPLES_PER_THREAD;
+        let summary = samples.summary();
+        assert_eq!(summary.len(), total as usize);
+        assert_eq!(summary.average(), Some(f64::from(total - 1) / 2.0));
+        assert_eq!(summary.min(), Some(0));
+        assert_eq!(summary.max(), Some(total - 1));
+    }
+
+    /*
+        Compared against the single Arc<Mutex<_>> from chapter 16, here wrapped around
+        an AveragedCollection, while another thread keeps reading summaries.
+    */
+    #[test]
+    fn matches_a_mutex_collection_while_being_read() {
+        let samples = Arc::new(SharedAveragedCollection::new());
+        let reference = Arc::new(Mutex::new(AveragedCollection::new()));
+        let mut handles = vec![];
+
+        for thread in 0..THREADS {
+            let samples = Arc::clone(&samples);
+            let reference = Arc::clone(&reference);
+            let handle = thread::spawn(move || {
+                for i in 0..SAMPLES_PER_THREAD {
+                    let value = f64::from(i % 100) * 0.5 - thread as f64;
+                    samples.add(value);
+                    reference.lock().unwrap().add(value);
+                }
+            });
+            handles.push(handle);
+        }
+
+        /*
+            The reader stops once the writers are done, rather than waiting for the
+            count to reach the total: if a writer panicked, that total would never come.
+        */
+        let writing = Arc::new(AtomicBool::new(true));
+        let reader = {
+            let samples = Arc::clone(&samples);
+            let writing = Arc::clone(&writing);
+            thread::spawn(move || {
+                let mut seen = 0;
+                while writing.load(Ordering::SeqCst) {
+                    let len = samples.len();
+                    assert!(len >= seen, "the count went back from {seen} to {len}");
+                    seen = len;
+                    thread::yield_now();
+                }
+            })
+        };
+
+        let written: Vec<_> = handles.into_iter().map(|handle| handle.join()).collect();
+        writing.store(false, Ordering::SeqCst);
+        reader.join().unwrap();
+        for result in written {
+            result.unwrap();
+        }
+
+        let summary = samples.summary();
+        let reference = reference.lock().unwrap();
+        assert_eq!(summary.len(), reference.len());
+        let close = |a: Option<f64>, b: Option<f64>| (a.unwrap() - b.unwrap()).abs() < 1e-9;
+        assert!(close(summary.average(), reference.average()));
+        assert!(close(summary.variance(), reference.variance()));
+        assert_eq!(summary.min(), reference.min());
+        assert_eq!(summary.max(), reference.max());
+    }
+}
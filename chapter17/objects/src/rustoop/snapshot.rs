@@ -0,0 +1,291 @@
+/*
+    Snapshot tests for what a Screen draws. Because the Screen renders into a Canvas in
+    memory, a test can render a frame at a fixed size and compare it with a text file
+    stored next to the sources, in the snapshots directory of the crate.
+
+    A snapshot holds the characters of the frame, followed by a map of their styles so
+    that losing a highlight also fails the test:
+
+        ┌──────┐
+        │  OK  │
+        └──────┘
+        --- styles
+        rrrrrrrr
+        r  bb  r
+        rrrrrrrr
+
+    In the style map a space is plain, b is bold, r is reverse and B is both.
+
+    When a change to the drawing code is intended, run the tests with UPDATE_SNAPSHOTS=1
+    to write the new frames into the snapshot files instead of comparing, then review
+    the changes to the files before committing them.
+*/
+
+use super::canvas::{Canvas, Style};
+use super::Screen;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const STYLE_SEPARATOR: &str = "--- styles\n";
+
+/*
+    Renders the screen at its current size into the snapshot format.
+*/
+pub fn render_frame(screen: &Screen) -> String {
+    frame_text(&screen.render())
+}
+
+pub fn frame_text(canvas: &Canvas) -> String {
+    let mut frame = canvas.to_text();
+    frame.push_str(STYLE_SEPARATOR);
+    for y in 0..canvas.height() {
+        for x in 0..canvas.width() {
+            let style = canvas.get(x, y).map_or(Style::PLAIN, |cell| cell.style);
+            frame.push(match (style.bold, style.reverse) {
+                (false, false) => ' ',
+                (true, false) => 'b',
+                (false, true) => 'r',
+                (true, true) => 'B',
+            });
+        }
+        frame.push('\n');
+    }
+    frame
+}
+
+pub fn snapshot_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("snapshots")
+}
+
+pub fn update_mode() -> bool {
+    env::var("UPDATE_SNAPSHOTS").is_ok_and(|value| !value.is_empty() && value != "0")
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    InvalidName(String),
+    Missing(PathBuf),
+    Mismatch { path: PathBuf, diff: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "snapshot I/O error: {error}"),
+            SnapshotError::InvalidName(name) => write!(f, "invalid snapshot name: {name:?}"),
+            SnapshotError::Missing(path) => write!(
+                f,
+                "no snapshot at {}; run with UPDATE_SNAPSHOTS=1 to create it",
+                path.display()
+            ),
+            SnapshotError::Mismatch { path, diff } => write!(
+                f,
+                "frame differs from {} (- expected, + actual):\n{diff}\
+                 run with UPDATE_SNAPSHOTS=1 if the change is intended",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> SnapshotError {
+        SnapshotError::Io(error)
+    }
+}
+
+/*
+    Compares a frame with the snapshot called name in dir, or overwrites the snapshot
+    with it in update mode. Names become file names, so they are limited to letters,
+    digits, - and _.
+*/
+pub fn check(dir: &Path, name: &str, frame: &str, update: bool) -> Result<(), SnapshotError> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(SnapshotError::InvalidName(String::from(name)));
+    }
+    let path = dir.join(format!("{name}.txt"));
+
+    if update {
+        fs::create_dir_all(dir)?;
+        fs::write(&path, frame)?;
+        return Ok(());
+    }
+
+    let expected = match fs::read_to_string(&path) {
+        Ok(expected) => expected,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Err(SnapshotError::Missing(path));
+        }
+        Err(error) => return Err(SnapshotError::Io(error)),
+    };
+    if expected == frame {
+        Ok(())
+    } else {
+        Err(SnapshotError::Mismatch {
+            path,
+            diff: diff(&expected, frame),
+        })
+    }
+}
+
+/*
+    The snapshot test itself: panics with the differing lines when the frame changed.
+*/
+pub fn assert_snapshot(name: &str, screen: &Screen) {
+    if let Err(error) = check(&snapshot_dir(), name, &render_frame(screen), update_mode()) {
+        panic!("{error}");
+    }
+}
+
+/*
+    Frames have the same number of lines when the size didn't change, so comparing
+    them line by line is enough to point at what moved. The line numbers are counted
+    from 1 like an editor does.
+*/
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut diff = String::new();
+    for line in 0..expected.len().max(actual.len()) {
+        let (old, new) = (expected.get(line), actual.get(line));
+        if old == new {
+            continue;
+        }
+        if let Some(old) = old {
+            diff.push_str(&format!("{:>4} - {old}\n", line + 1));
+        }
+        if let Some(new) = new {
+            diff.push_str(&format!("{:>4} + {new}\n", line + 1));
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::canvas::Rect;
+    use super::super::components::{Button, Label, SelectBox};
+    use super::super::events::{Event, Key};
+    use super::super::layout::{Constraint, Grid, Stack};
+    use super::*;
+    use std::process;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = env::temp_dir().join(format!("snapshots-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn frames_include_a_style_map() {
+        let mut screen = Screen::new(8, 3);
+        screen.add(Rect::new(0, 0, 8, 3), Button::new("OK"));
+        screen.focus_next();
+
+        assert_eq!(
+            render_frame(&screen),
+            "┌──────┐\n│  OK  │\n└──────┘\n--- styles\nrrrrrrrr\nr  bb  r\nrrrrrrrr\n"
+        );
+    }
+
+    #[test]
+    fn update_mode_writes_and_comparing_passes() {
+        let temp = TempDir::new("update");
+        assert!(matches!(
+            check(&temp.0, "frame", "ab\n", false),
+            Err(SnapshotError::Missing(_))
+        ));
+
+        check(&temp.0, "frame", "ab\n", true).unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.0.join("frame.txt")).unwrap(),
+            "ab\n"
+        );
+        check(&temp.0, "frame", "ab\n", false).unwrap();
+    }
+
+    #[test]
+    fn mismatches_report_the_changed_lines() {
+        let temp = TempDir::new("mismatch");
+        check(&temp.0, "frame", "same\nold\nsame\n", true).unwrap();
+
+        let error = check(&temp.0, "frame", "same\nnew\nsame\nextra\n", false).unwrap_err();
+        match &error {
+            SnapshotError::Mismatch { diff, .. } => {
+                assert_eq!(diff, "   2 - old\n   2 + new\n   4 + extra\n")
+            }
+            other => panic!("unexpected error: {other}"),
+        }
+        assert!(error.to_string().contains("UPDATE_SNAPSHOTS=1"));
+    }
+
+    #[test]
+    fn names_cannot_leave_the_snapshot_directory() {
+        let temp = TempDir::new("names");
+        for name in ["", "../escape", "a/b", "a.txt"] {
+            assert!(matches!(
+                check(&temp.0, name, "x\n", true),
+                Err(SnapshotError::InvalidName(_))
+            ));
+        }
+    }
+
+    fn order_form() -> Screen {
+        let buttons = Grid::new(&[Constraint::Fill], &[Constraint::Fill, Constraint::Fill])
+            .with(0, 0, Button::new("Order"))
+            .with(0, 1, Button::new("Cancel"));
+        let mut screen = Screen::new(24, 8);
+        screen.add_full(
+            Stack::vertical()
+                .with(Constraint::Fixed(1), Label::new("Pick a size:"))
+                .with(
+                    Constraint::Fill,
+                    SelectBox::new(&["Small", "Medium", "Large"]),
+                )
+                .with(Constraint::Fixed(3), buttons),
+        );
+        screen
+    }
+
+    #[test]
+    fn order_form_snapshot() {
+        assert_snapshot("order_form", &order_form());
+    }
+
+    #[test]
+    fn order_form_after_input_snapshot() {
+        let mut screen = order_form();
+        for key in [Key::Tab, Key::Down, Key::Down, Key::Tab] {
+            screen.dispatch(&Event::Key(key));
+        }
+        assert_snapshot("order_form_after_input", &screen);
+    }
+
+    #[test]
+    fn order_form_resized_snapshot() {
+        let mut screen = order_form();
+        screen.resize(16, 7);
+        assert_snapshot("order_form_resized", &screen);
+    }
+}
@@ -0,0 +1,612 @@
+/*
+    Helpers that let AveragedCollection keep its statistics up to date as values come
+    and go, instead of walking through the whole list each time something changes.
+*/
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::{Hash, Hasher};
+
+/*
+    The numeric types an AveragedCollection can hold. Each one says how to turn its
+    values into f64 for the results, how to order them (floats only have a partial
+    order, so they provide a key with a total one), and which accumulator keeps
+    their running sums without overflowing.
+*/
+pub trait Sample: Copy {
+    type Key: Ord + Hash + Copy;
+    type Accumulator: Accumulator<Self>;
+
+    fn to_f64(self) -> f64;
+    fn key(self) -> Self::Key;
+}
+
+/*
+    Running totals that give the mean and the population variance of the values
+    added so far. Values are removed by passing them back to remove, and merge adds
+    in everything another accumulator has seen, as if its values had been added here.
+*/
+pub trait Accumulator<T>: Default + Clone {
+    fn add(&mut self, value: T);
+    fn remove(&mut self, value: T);
+    fn merge(&mut self, other: &Self);
+    fn mean(&self) -> Option<f64>;
+    fn variance(&self) -> Option<f64>;
+}
+
+/*
+    Exact sums in i128 for integers of up to 32 bits. A square of such a value fits in
+    64 bits, so the sums themselves could only overflow after 2^63 values. The variance
+    (n * sum of squares - sum * sum) / n² stays exact until the final division, but its
+    products overflow an i128 after about 3 billion values as large as u32::MAX.
+    Past that, variance falls back to Welford's m2, the sum of squared distances from
+    the mean, worked out from the sums with the mean split into a quotient and a
+    remainder so nothing grows beyond the sum of squares.
+*/
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExactSums {
+    count: i128,
+    sum: i128,
+    sum_of_squares: i128,
+}
+
+impl<T: Into<i128>> Accumulator<T> for ExactSums {
+    fn add(&mut self, value: T) {
+        let value = value.into();
+        self.count += 1;
+        self.sum += value;
+        self.sum_of_squares += value * value;
+    }
+
+    fn remove(&mut self, value: T) {
+        let value = value.into();
+        self.count -= 1;
+        self.sum -= value;
+        self.sum_of_squares -= value * value;
+    }
+
+    fn merge(&mut self, other: &ExactSums) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.sum_of_squares += other.sum_of_squares;
+    }
+
+    fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum as f64 / self.count as f64)
+    }
+
+    fn variance(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let n = self.count;
+        let spread = n
+            .checked_mul(self.sum_of_squares)
+            .zip(self.sum.checked_mul(self.sum))
+            .and_then(|(squares, sum)| squares.checked_sub(sum));
+        match spread {
+            Some(spread) => Some(spread as f64 / n as f64 / n as f64),
+            None => {
+                // sum = q * n + r, so m2 = sum of squares - sum² / n
+                //                       = sum of squares - q² * n - 2 * q * r - r² / n
+                let (q, r) = (self.sum.div_euclid(n), self.sum.rem_euclid(n));
+                let m2 = self.sum_of_squares - q * q * n - 2 * q * r;
+                Some((m2 as f64 - (r * r) as f64 / n as f64) / n as f64)
+            }
+        }
+    }
+}
+
+/*
+    Welford's method for 64 bit integers and floats, whose squares could overflow even
+    an i128. Instead of sums it keeps the mean and the sum of squared distances from it
+    (m2) in f64, updating both from each value, so they never grow much larger than the
+    values themselves. Each step can be undone, which is what remove does.
+*/
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Welford {
+    count: usize,
+    mean: f64,
+    m2: f64,
+}
+
+impl<T: Sample> Accumulator<T> for Welford {
+    fn add(&mut self, value: T) {
+        let value = value.to_f64();
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    fn remove(&mut self, value: T) {
+        let value = value.to_f64();
+        self.count -= 1;
+        if self.count == 0 {
+            *self = Welford::default();
+            return;
+        }
+        let previous = self.mean;
+        self.mean -= (value - self.mean) / self.count as f64;
+        self.m2 = (self.m2 - (value - self.mean) * (value - previous)).max(0.0);
+    }
+
+    /*
+        Chan's formula for combining two groups: the means are weighted by the group
+        sizes, and m2 gains a term for how far apart the two means are.
+    */
+    fn merge(&mut self, other: &Welford) {
+        if other.count == 0 {
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        let (own, theirs) = (self.count as f64, other.count as f64);
+        self.mean += delta * theirs / count as f64;
+        self.m2 += other.m2 + delta * delta * own * theirs / count as f64;
+        self.count = count;
+    }
+
+    fn mean(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.mean)
+    }
+
+    fn variance(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.m2 / self.count as f64)
+    }
+}
+
+macro_rules! integer_samples {
+    ($accumulator:ty: $($t:ty),+) => {
+        $(
+            impl Sample for $t {
+                type Key = $t;
+                type Accumulator = $accumulator;
+
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+
+                fn key(self) -> $t {
+                    self
+                }
+            }
+        )+
+    };
+}
+
+integer_samples!(ExactSums: i8, i16, i32, u8, u16, u32);
+integer_samples!(Welford: i64, u64);
+
+/*
+    The bits of a float, read as an integer, sort the same way as the floats do when
+    the sign bit is clear. Flipping the other bits of negative numbers makes them sort
+    below, in the right order. This is the total order of f64::total_cmp.
+*/
+impl Sample for f64 {
+    type Key = i64;
+    type Accumulator = Welford;
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn key(self) -> i64 {
+        let bits = self.to_bits() as i64;
+        bits ^ (((bits >> 63) as u64) >> 1) as i64
+    }
+}
+
+impl Sample for f32 {
+    type Key = i64;
+    type Accumulator = Welford;
+
+    fn to_f64(self) -> f64 {
+        f64::from(self)
+    }
+
+    fn key(self) -> i64 {
+        f64::from(self).key()
+    }
+}
+
+/*
+    Wraps a sample so it can go into a BinaryHeap, which needs Ord.
+*/
+#[derive(Debug, Clone, Copy)]
+struct Ordered<T: Sample>(T);
+
+impl<T: Sample> PartialEq for Ordered<T> {
+    fn eq(&self, other: &Ordered<T>) -> bool {
+        self.0.key() == other.0.key()
+    }
+}
+
+impl<T: Sample> Eq for Ordered<T> {}
+
+impl<T: Sample> PartialOrd for Ordered<T> {
+    fn partial_cmp(&self, other: &Ordered<T>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Sample> Ord for Ordered<T> {
+    fn cmp(&self, other: &Ordered<T>) -> Ordering {
+        self.0.key().cmp(&other.0.key())
+    }
+}
+
+impl<T: Sample> Hash for Ordered<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.key().hash(state);
+    }
+}
+
+/*
+    Keeps the median of a multiset of values with two heaps: a max-heap holding the
+    smaller half and a min-heap holding the larger half. The median is at the top of
+    one or both heaps, and adding a value only moves at most one value between them.
+
+    A BinaryHeap can only remove its top, so removing any other value is done lazily:
+    the value is counted in deleted and thrown away once it reaches the top of a heap.
+    The live lengths leave those values out, so the halves stay balanced.
+*/
+#[derive(Debug)]
+pub struct RunningMedian<T: Sample> {
+    low: BinaryHeap<Ordered<T>>,
+    high: BinaryHeap<Reverse<Ordered<T>>>,
+    low_len: usize,
+    high_len: usize,
+    deleted: HashMap<T::Key, usize>,
+}
+
+impl<T: Sample> RunningMedian<T> {
+    pub fn new() -> RunningMedian<T> {
+        RunningMedian {
+            low: BinaryHeap::new(),
+            high: BinaryHeap::new(),
+            low_len: 0,
+            high_len: 0,
+            deleted: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.low_len + self.high_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&mut self, value: T) {
+        let value = Ordered(value);
+        match self.low.peek() {
+            Some(top) if value > *top => {
+                self.high.push(Reverse(value));
+                self.high_len += 1;
+            }
+            _ => {
+                self.low.push(value);
+                self.low_len += 1;
+            }
+        }
+        self.rebalance();
+    }
+
+    /*
+        Removes one copy of value, which must have been inserted before. Values no
+        greater than the top of the lower half live in that half; the rest are above.
+    */
+    pub fn remove(&mut self, value: T) {
+        *self.deleted.entry(value.key()).or_insert(0) += 1;
+        match self.low.peek() {
+            Some(top) if Ordered(value) <= *top => self.low_len -= 1,
+            _ => self.high_len -= 1,
+        }
+        self.prune();
+        self.rebalance();
+    }
+
+    pub fn median(&self) -> Option<f64> {
+        match (self.low.peek(), self.high.peek()) {
+            _ if self.is_empty() => None,
+            (Some(low), Some(Reverse(high))) if self.low_len == self.high_len => {
+                Some((low.0.to_f64() + high.0.to_f64()) / 2.0)
+            }
+            (Some(low), _) => Some(low.0.to_f64()),
+            (None, _) => None,
+        }
+    }
+
+    /*
+        The lower half holds as many values as the upper half, or one more.
+    */
+    fn rebalance(&mut self) {
+        if self.low_len > self.high_len + 1 {
+            if let Some(value) = self.low.pop() {
+                self.high.push(Reverse(value));
+                self.low_len -= 1;
+                self.high_len += 1;
+            }
+        } else if self.low_len < self.high_len {
+            if let Some(Reverse(value)) = self.high.pop() {
+                self.low.push(value);
+                self.high_len -= 1;
+                self.low_len += 1;
+            }
+        }
+        self.prune();
+    }
+
+    /*
+        Drops deleted values from the tops of both heaps, so that the tops are live.
+    */
+    fn prune(&mut self) {
+        while let Some(top) = self.low.peek().copied() {
+            if !self.take_deleted(top.0) {
+                break;
+            }
+            self.low.pop();
+        }
+        while let Some(Reverse(top)) = self.high.peek().copied() {
+            if !self.take_deleted(top.0) {
+                break;
+            }
+            self.high.pop();
+        }
+    }
+
+    fn take_deleted(&mut self, value: T) -> bool {
+        match self.deleted.get_mut(&value.key()) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 0 {
+                    self.deleted.remove(&value.key());
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<T: Sample> Default for RunningMedian<T> {
+    fn default() -> RunningMedian<T> {
+        RunningMedian::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::AveragedCollection;
+    use super::*;
+
+    fn close(actual: Option<f64>, expected: f64) -> bool {
+        actual.is_some_and(|actual| (actual - expected).abs() < 1e-9)
+    }
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        let mut median = RunningMedian::<i32>::new();
+        assert_eq!(median.median(), None);
+        for value in [5, 1, 9] {
+            median.insert(value);
+        }
+        assert_eq!(median.median(), Some(5.0));
+        median.insert(3);
+        assert_eq!(median.median(), Some(4.0));
+    }
+
+    #[test]
+    fn median_survives_removals_from_either_half() {
+        let mut median = RunningMedian::new();
+        for value in [4, 4, 1, 8, 4, 7, 2] {
+            median.insert(value);
+        }
+        median.remove(8);
+        median.remove(4);
+        median.remove(1);
+        assert_eq!(median.len(), 4);
+        assert_eq!(median.median(), Some(4.0)); // 2, 4, 4, 7
+        for value in [2, 4, 4, 7] {
+            median.remove(value);
+        }
+        assert!(median.is_empty());
+        assert_eq!(median.median(), None);
+    }
+
+    #[test]
+    fn median_matches_sorting() {
+        let mut median = RunningMedian::new();
+        let mut values = Vec::new();
+        let mut seed: u32 = 17;
+        for step in 0..500 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let value = (seed >> 16) as i32 % 50 - 25;
+            if step % 3 == 2 {
+                let removed = values.swap_remove(value.unsigned_abs() as usize % values.len());
+                median.remove(removed);
+            } else {
+                values.push(value);
+                median.insert(value);
+            }
+            let mut sorted = values.clone();
+            sorted.sort();
+            let n = sorted.len();
+            let expected = if n % 2 == 1 {
+                f64::from(sorted[n / 2])
+            } else {
+                (f64::from(sorted[n / 2 - 1]) + f64::from(sorted[n / 2])) / 2.0
+            };
+            assert_eq!(median.median(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn float_keys_sort_like_floats() {
+        let values = [
+            f64::NEG_INFINITY,
+            -2.5,
+            -0.0,
+            0.0,
+            1e-300,
+            3.0,
+            f64::INFINITY,
+        ];
+        for pair in values.windows(2) {
+            assert!(pair[0].key() < pair[1].key());
+        }
+    }
+
+    #[test]
+    fn welford_undoes_each_step() {
+        let mut welford = Welford::default();
+        for value in [1.5, 2.5, 10.0] {
+            welford.add(value);
+        }
+        Accumulator::<f64>::remove(&mut welford, 10.0);
+        assert!(close(Accumulator::<f64>::mean(&welford), 2.0));
+        assert!(close(Accumulator::<f64>::variance(&welford), 0.25));
+    }
+
+    #[test]
+    fn exact_sums_variance_survives_products_beyond_i128() {
+        // Four billion values of u32::MAX and four billion zeros.
+        let half = 4_000_000_000;
+        let max = i128::from(u32::MAX);
+        let sums = ExactSums {
+            count: 2 * half,
+            sum: half * max,
+            sum_of_squares: half * max * max,
+        };
+        assert!(sums.count.checked_mul(sums.sum_of_squares).is_none());
+
+        let expected = (max as f64 / 2.0).powi(2);
+        let variance = Accumulator::<u32>::variance(&sums).unwrap();
+        assert!((variance / expected - 1.0).abs() < 1e-12);
+        assert!(close(Accumulator::<u32>::mean(&sums), max as f64 / 2.0));
+    }
+
+    #[test]
+    fn merged_accumulators_match_adding_everything_to_one() {
+        let (mut left, mut right, mut all) =
+            (Welford::default(), Welford::default(), Welford::default());
+        let (mut exact_left, mut exact_right) = (ExactSums::default(), ExactSums::default());
+        for value in [1.0, 2.0, 4.0] {
+            left.add(value);
+            all.add(value);
+            exact_left.add(value as i32);
+        }
+        for value in [8.0, 16.0] {
+            right.add(value);
+            all.add(value);
+            exact_right.add(value as i32);
+        }
+        Accumulator::<f64>::merge(&mut left, &right);
+        Accumulator::<i32>::merge(&mut exact_left, &exact_right);
+
+        assert!(close(Accumulator::<f64>::mean(&left), 6.2));
+        assert!(close(
+            Accumulator::<f64>::variance(&left),
+            Accumulator::<f64>::variance(&all).unwrap()
+        ));
+        assert!(close(Accumulator::<i32>::variance(&exact_left), 29.76));
+        Accumulator::<f64>::merge(&mut left, &Welford::default());
+        assert!(close(Accumulator::<f64>::mean(&left), 6.2));
+    }
+
+    #[test]
+    fn collection_keeps_every_statistic() {
+        let mut collection = AveragedCollection::new();
+        assert_eq!(collection.average(), None);
+        assert_eq!(collection.min(), None);
+        assert_eq!(collection.variance(), None);
+        assert_eq!(collection.median(), None);
+
+        for value in [2, 4, 4, 4, 5, 5, 7, 9] {
+            collection.add(value);
+        }
+        assert_eq!(collection.average(), Some(5.0));
+        assert!(close(collection.variance(), 4.0));
+        assert!(close(collection.standard_deviation(), 2.0));
+        assert_eq!(collection.min(), Some(2));
+        assert_eq!(collection.max(), Some(9));
+        assert_eq!(collection.median(), Some(4.5));
+    }
+
+    #[test]
+    fn statistics_follow_removals() {
+        let mut collection = AveragedCollection::new();
+        for value in [3, -10, 8, 1] {
+            collection.add(value);
+        }
+        assert_eq!(collection.remove(), Some(1));
+        assert_eq!(collection.remove(), Some(8));
+
+        assert_eq!(collection.average(), Some(-3.5));
+        assert!(close(collection.variance(), 42.25));
+        assert_eq!(collection.min(), Some(-10));
+        assert_eq!(collection.max(), Some(3));
+        assert_eq!(collection.median(), Some(-3.5));
+
+        collection.remove();
+        collection.remove();
+        assert_eq!(collection.remove(), None);
+        assert_eq!(collection.average(), None);
+        assert_eq!(collection.max(), None);
+        assert_eq!(collection.median(), None);
+    }
+
+    #[test]
+    fn sums_do_not_overflow_i32() {
+        let mut collection = AveragedCollection::new();
+        collection.add(i32::MAX);
+        collection.add(i32::MAX);
+        assert_eq!(collection.average(), Some(f64::from(i32::MAX)));
+        assert!(close(collection.variance(), 0.0));
+    }
+
+    #[test]
+    fn sums_do_not_overflow_64_bit_integers() {
+        let mut collection: AveragedCollection<u64> = AveragedCollection::new();
+        for _ in 0..4 {
+            collection.add(u64::MAX);
+        }
+        collection.add(0);
+        assert!(close(collection.average(), u64::MAX as f64 * 0.8));
+        assert_eq!(collection.min(), Some(0));
+        assert_eq!(collection.median(), Some(u64::MAX as f64));
+
+        let mut collection = AveragedCollection::new();
+        collection.add(i64::MIN);
+        collection.add(i64::MAX);
+        assert!(collection.average().unwrap().abs() < 1.0);
+    }
+
+    #[test]
+    fn holds_floats() {
+        let mut collection = AveragedCollection::new();
+        for value in [0.5, -1.25, 4.0, 0.5] {
+            collection.add(value);
+        }
+        assert_eq!(collection.min(), Some(-1.25));
+        assert_eq!(collection.max(), Some(4.0));
+        assert_eq!(collection.median(), Some(0.5));
+        assert!(close(collection.average(), 0.9375));
+        assert!(close(collection.variance(), 3.63671875));
+
+        collection.remove();
+        assert!(close(collection.average(), 1.0833333333333333));
+    }
+
+    #[test]
+    fn holds_small_integers() {
+        let mut collection: AveragedCollection<u8> = AveragedCollection::new();
+        for _ in 0..1000 {
+            collection.add(255);
+        }
+        assert_eq!(collection.average(), Some(255.0));
+        assert_eq!(collection.standard_deviation(), Some(0.0));
+    }
+}
@@ -8,17 +8,100 @@ used in almost every single Rust program.
 use std::io; // importing a standard library not in the prelude
 use rand::Rng; // The Rng trait defines methods that random number generators implement
 use std::cmp::Ordering;
+use std::fmt;
+use std::num::IntErrorKind;
+
+const MIN_GUESS: u32 = 1;
+const MAX_GUESS: u32 = 100;
+
+/*
+    Instead of silently ignoring bad input, we describe every way a guess can be
+    invalid with an enum. Each variant carries enough data to explain the problem
+    back to the player.
+*/
+#[derive(Debug)]
+enum GuessError {
+    Empty,
+    NotANumber(String),
+    Negative(String),
+    OutOfRange(String),
+}
+
+/*
+    Implementing Display lets us print a GuessError with {} just like a String.
+*/
+impl fmt::Display for GuessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GuessError::Empty => write!(f, "Please type a number before pressing enter."),
+            GuessError::NotANumber(input) => write!(f, "'{input}' is not a number."),
+            GuessError::Negative(input) => {
+                write!(f, "{input} is negative; the secret number is never below {MIN_GUESS}.")
+            }
+            GuessError::OutOfRange(input) => {
+                write!(f, "{input} is out of range; guess between {MIN_GUESS} and {MAX_GUESS}.")
+            }
+        }
+    }
+}
+
+/*
+    The player can either make a guess or ask to leave the game.
+*/
+enum Command {
+    Guess(u32),
+    Quit,
+}
+
+fn parse_command(input: &str) -> Result<Command, GuessError> {
+    let input = input.trim();
+
+    if input.eq_ignore_ascii_case("quit") {
+        return Ok(Command::Quit);
+    }
+
+    parse_guess(input).map(Command::Guess)
+}
+
+fn parse_guess(input: &str) -> Result<u32, GuessError> {
+    if input.is_empty() {
+        return Err(GuessError::Empty);
+    }
+
+    /*
+        Parsing into a signed type first lets us tell a negative number apart from
+        text that isn't a number at all. The kind method on ParseIntError tells us
+        why parsing failed, so values too large for i64 are still reported as out of range.
+    */
+    let guess: i64 = match input.parse() {
+        Ok(num) => num,
+        Err(e) => match e.kind() {
+            IntErrorKind::PosOverflow => return Err(GuessError::OutOfRange(input.to_string())),
+            IntErrorKind::NegOverflow => return Err(GuessError::Negative(input.to_string())),
+            _ => return Err(GuessError::NotANumber(input.to_string())),
+        },
+    };
+
+    if guess < 0 {
+        return Err(GuessError::Negative(input.to_string()));
+    }
+    if guess < MIN_GUESS as i64 || guess > MAX_GUESS as i64 {
+        return Err(GuessError::OutOfRange(input.to_string()));
+    }
+
+    Ok(guess as u32)
+}
 
 fn main() {
     println!("Guess the number!");
 
-    let secret_number = rand::thread_rng().gen_range(1..=100); // inclusive of bounds
+    let secret_number = rand::thread_rng().gen_range(MIN_GUESS..=MAX_GUESS); // inclusive of bounds
     /*
         gen function contained in Rng trait, which is imported above
     */
 
     loop {
-        println!("Please input your guess.");
+        println!("Please input your guess (or type quit to leave).");
 
         let mut guess = String::new(); // make a variable mutable
         /*
@@ -26,10 +109,20 @@ fn main() {
             An associated function is a function that’s implemented on a type, in this case String
         */
 
-        io::stdin()
+        let bytes_read = io::stdin()
             .read_line(&mut guess)
             .expect("Failed to read line");
 
+        /*
+            read_line returns the number of bytes read; 0 means we reached the end of
+            the input (for example, when input is piped in from a file), so we stop
+            instead of looping forever.
+        */
+        if bytes_read == 0 {
+            println!("No more input, goodbye!");
+            break;
+        }
+
         /*
             The stdin function returns an instance of std::io::Stdin, which is a 
             type that represents a handle to the standard input for your terminal.
@@ -44,18 +137,25 @@ fn main() {
             crash and display the message that you passed as an argument to expect
         */
 
-        let guess: u32 = match guess.trim().parse() {
-            Ok(num) => num,
-            Err(_) => continue,
+        let guess: u32 = match parse_command(&guess) {
+            Ok(Command::Guess(num)) => num,
+            Ok(Command::Quit) => {
+                println!("The secret number was {secret_number}. Goodbye!");
+                break;
+            }
+            Err(e) => {
+                println!("{e}");
+                continue;
+            }
         };
         
         /*
             Variable shadowing allows us to reuse the previous variable name with a different
             type.
             u32 represents unsigned 32 bit integer, i32 is signed.
-            Note that since parse returns a Result type which is an enum with 2 variants, 
-            we can apply a match expression to continue the loop when any input error arises;
-            underscore is a catchall value.
+            Note that since parse_command returns a Result type which is an enum with 2 variants, 
+            we can apply a match expression to report the GuessError and continue the loop
+            when any input error arises.
         */
 
         println!("You guessed: {guess}");
@@ -86,3 +186,53 @@ fn main() {
         */
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_guesses_within_range() {
+        assert!(matches!(parse_guess("1"), Ok(1)));
+        assert!(matches!(parse_guess("100"), Ok(100)));
+        assert!(matches!(parse_command("  42\n"), Ok(Command::Guess(42))));
+    }
+
+    #[test]
+    fn rejects_guesses_out_of_range() {
+        assert!(matches!(parse_guess("0"), Err(GuessError::OutOfRange(_))));
+        assert!(matches!(parse_guess("101"), Err(GuessError::OutOfRange(_))));
+        assert!(matches!(
+            parse_guess("99999999999999999999"),
+            Err(GuessError::OutOfRange(_))
+        ));
+        assert!(matches!(parse_guess("-5"), Err(GuessError::Negative(_))));
+        assert!(matches!(
+            parse_guess("-99999999999999999999"),
+            Err(GuessError::Negative(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_input_that_is_not_a_number() {
+        assert!(matches!(
+            parse_guess("fifty"),
+            Err(GuessError::NotANumber(input)) if input == "fifty"
+        ));
+        assert!(matches!(parse_guess("4.5"), Err(GuessError::NotANumber(_))));
+        assert!(matches!(parse_command("quitter"), Err(GuessError::NotANumber(_))));
+    }
+
+    #[test]
+    fn quit_ignores_case_and_whitespace() {
+        assert!(matches!(parse_command("quit\n"), Ok(Command::Quit)));
+        assert!(matches!(parse_command("  QUIT  "), Ok(Command::Quit)));
+    }
+
+    #[test]
+    fn blank_input_is_empty() {
+        assert!(matches!(parse_guess(""), Err(GuessError::Empty)));
+        assert!(matches!(parse_command("\n"), Err(GuessError::Empty)));
+        assert!(matches!(parse_command("   \r\n"), Err(GuessError::Empty)));
+    }
+}